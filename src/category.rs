@@ -0,0 +1,165 @@
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+// What an ingredient is, beyond being flour or not. The order is the one
+// ingredients are grouped in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Category {
+    Flour,
+    Liquid,
+    Egg,
+    Fat,
+    Sugar,
+    Salt,
+    Leavening,
+    Inclusion,
+    Other,
+}
+
+impl Category {
+    pub const ALL: [Category; 9] = [
+        Category::Flour,
+        Category::Liquid,
+        Category::Egg,
+        Category::Fat,
+        Category::Sugar,
+        Category::Salt,
+        Category::Leavening,
+        Category::Inclusion,
+        Category::Other,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Category::Flour => "flour",
+            Category::Liquid => "liquid",
+            Category::Egg => "egg",
+            Category::Fat => "fat",
+            Category::Sugar => "sugar",
+            Category::Salt => "salt",
+            Category::Leavening => "leavening",
+            Category::Inclusion => "inclusion",
+            Category::Other => "other",
+        }
+    }
+
+    // the fraction of the mass of an ingredient of the category that counts
+    // as water towards the hydration of a dough, unless the ingredient is
+    // known to hold a different amount, see water_fraction. Eggs are about
+    // three quarters water
+    pub fn default_water_fraction(&self) -> Decimal {
+        match self {
+            Category::Liquid => dec!(1),
+            Category::Egg => dec!(0.75),
+            _ => dec!(0),
+        }
+    }
+
+    // Returns the category of an ingredient that is not given one: flour if
+    // it is flour, otherwise looked up by its name and other if the name is
+    // unknown. Names are compared ignoring case and surrounding whitespace
+    pub fn infer(ingredient: &str, is_flour: bool) -> Category {
+        if is_flour {
+            return Category::Flour;
+        }
+        let ingredient = ingredient.trim().to_lowercase();
+        KNOWN_INGREDIENTS
+            .iter()
+            .find(|(name, _)| *name == ingredient)
+            .map_or(Category::Other, |(_, category)| *category)
+    }
+}
+
+// Returns the fraction of the mass of an ingredient that is water, looked up
+// by its name and otherwise given by its category
+pub fn water_fraction(ingredient: &str, category: Category) -> Decimal {
+    let ingredient = ingredient.trim().to_lowercase();
+    WATER_FRACTIONS
+        .iter()
+        .find(|(name, _)| *name == ingredient)
+        .map_or(category.default_water_fraction(), |(_, fraction)| *fraction)
+}
+
+impl fmt::Display for Category {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Category {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Category::ALL.into_iter().find(|category| category.name() == s).ok_or(())
+    }
+}
+
+// categories of common ingredients, by lower case name
+const KNOWN_INGREDIENTS: [(&str, Category); 24] = [
+    ("water", Category::Liquid),
+    ("milk", Category::Liquid),
+    ("whole milk", Category::Liquid),
+    ("buttermilk", Category::Liquid),
+    ("cream", Category::Liquid),
+    ("egg", Category::Egg),
+    ("eggs", Category::Egg),
+    ("egg yolk", Category::Egg),
+    ("butter", Category::Fat),
+    ("oil", Category::Fat),
+    ("vegetable oil", Category::Fat),
+    ("olive oil", Category::Fat),
+    ("lard", Category::Fat),
+    ("sugar", Category::Sugar),
+    ("honey", Category::Sugar),
+    ("malt syrup", Category::Sugar),
+    ("maple syrup", Category::Sugar),
+    ("salt", Category::Salt),
+    ("sea salt", Category::Salt),
+    ("yeast", Category::Leavening),
+    ("instant yeast", Category::Leavening),
+    ("active dry yeast", Category::Leavening),
+    ("fresh yeast", Category::Leavening),
+    ("baking powder", Category::Leavening),
+];
+
+// water fractions of common ingredients that are not all water, by lower
+// case name
+const WATER_FRACTIONS: [(&str, Decimal); 8] = [
+    ("milk", dec!(0.87)),
+    ("whole milk", dec!(0.87)),
+    ("buttermilk", dec!(0.9)),
+    ("cream", dec!(0.6)),
+    ("egg yolk", dec!(0.5)),
+    ("butter", dec!(0.16)),
+    ("honey", dec!(0.17)),
+    ("malt syrup", dec!(0.2)),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_infer() {
+        assert_eq!(Category::infer("Water ", false), Category::Liquid);
+        assert_eq!(Category::infer("water", true), Category::Flour);
+        assert_eq!(Category::infer("sunflower seeds", false), Category::Other);
+        for category in Category::ALL {
+            assert_eq!(category.name().parse(), Ok(category));
+        }
+        assert!("seasoning".parse::<Category>().is_err());
+        assert_eq!(serde_json::to_string(&Category::Leavening).unwrap(), "\"leavening\"");
+    }
+
+    #[test]
+    fn test_water_fraction() {
+        assert_eq!(water_fraction("Milk", Category::Liquid), dec!(0.87));
+        assert_eq!(water_fraction("butter", Category::Fat), dec!(0.16));
+        assert_eq!(water_fraction("eggs", Category::Egg), dec!(0.75));
+        assert_eq!(water_fraction("beer", Category::Liquid), dec!(1));
+        assert_eq!(water_fraction("bread flour", Category::Flour), dec!(0));
+    }
+}
@@ -1,5 +1,7 @@
+use crate::csv_dialect::CsvDialect;
+use crate::error::FormulaError;
 use core::fmt;
-use std::{collections::HashMap, cmp};
+use std::{collections::HashMap, cmp, str::FromStr};
 use rust_decimal::prelude::*;
 use rust_decimal_macros::dec;
 
@@ -10,17 +12,27 @@ pub struct CSVCell {
     pub position: CellPosition,
 }
 
+// Returns a String of the csv representation for the given cells, in the
+// default dialect
+pub fn csv_cells_to_grid(cells: &[CSVCell]) -> Result<String, FormulaError> {
+    csv_cells_to_grid_with_dialect(cells, &CsvDialect::default())
+}
+
 // Returns a String of the csv representation for the given cells
-pub fn csv_cells_to_grid(cells: &Vec<CSVCell>) -> String {
+pub fn csv_cells_to_grid_with_dialect(
+    cells: &[CSVCell],
+    dialect: &CsvDialect,
+) -> Result<String, FormulaError> {
+    dialect.validate()?;
     let mut grid: HashMap<u32, HashMap<u32, CellValue>> = HashMap::new();
     let mut max_row: u32 = 0;
     let mut max_col:u32 = 0;
     for cell in cells {
-        if !grid.contains_key(&cell.position.row) {
-            grid.insert(cell.position.row, HashMap::new());
-        }
+        grid.entry(cell.position.row).or_default();
         if grid[&cell.position.row].contains_key(&cell.position.col) {
-            panic!("csv grid cannot contain overlapping CSVCells")
+            return Err(FormulaError::OverlappingCells {
+                position: cell.position.clone(),
+            });
         }
         let x = cell.position.row;
         let y = cell.position.col;
@@ -33,20 +45,68 @@ pub fn csv_cells_to_grid(cells: &Vec<CSVCell>) -> String {
     for row in 0..(max_row + 1) {
         for col in 0..(max_col + 1) {
             if grid.contains_key(&row) && grid[&row].contains_key(&col) {
-                let val = &grid[&row][&col].to_string();
-                grid_string.push_str(val);
+                let val = &grid[&row][&col].format_with(dialect);
+                grid_string.push_str(&dialect.quote_field(val));
             }
             if col != max_col {
-                grid_string.push(',');
+                grid_string.push(dialect.delimiter);
             }
         }
-        grid_string.push('\n');
+        grid_string.push_str(&dialect.line_terminator);
     }
-    grid_string
+    Ok(grid_string)
 }
 
+// Returns the cells of a csv grid in the default dialect, the inverse of
+// csv_cells_to_grid.
+//  - fields starting with '=' are formulas
+//  - numbers such as "500" or "12.5%" are values, which is how spreadsheet
+//    applications export the result of a formula
+//  - any other field is text and empty fields are left out
+pub fn csv_grid_to_cells(csv: &str) -> Result<Vec<CSVCell>, FormulaError> {
+    csv_grid_to_cells_with_dialect(csv, &CsvDialect::default())
+}
 
-#[derive(Debug, Clone)]
+// Returns the cells of a csv grid written in dialect
+pub fn csv_grid_to_cells_with_dialect(
+    csv: &str,
+    dialect: &CsvDialect,
+) -> Result<Vec<CSVCell>, FormulaError> {
+    dialect.validate()?;
+    let mut cells: Vec<CSVCell> = Vec::new();
+    for (row, fields) in dialect.split_rows(csv)?.into_iter().enumerate() {
+        for (col, field) in fields.into_iter().enumerate() {
+            if field.is_empty() {
+                continue;
+            }
+            cells.push(CSVCell {
+                value: parse_csv_field(&field, dialect)?,
+                position: CellPosition { row: row as u32, col: col as u32 },
+            });
+        }
+    }
+    Ok(cells)
+}
+
+// returns the value of a single unquoted csv field
+fn parse_csv_field(field: &str, dialect: &CsvDialect) -> Result<CellValue, FormulaError> {
+    if field.starts_with('=') {
+        return Ok(CellValue::Expr(CellExpr::parse_with_dialect(field, dialect)?));
+    }
+    let trimmed = field.trim().replace(dialect.decimal_separator, ".");
+    let number = match trimmed.strip_suffix('%') {
+        Some(percent) => Decimal::from_str(percent.trim_end())
+            .ok()
+            .map(|x| CellExpr::Percentage(x / dec!(100))),
+        None => Decimal::from_str(&trimmed).ok().map(CellExpr::Number),
+    };
+    Ok(match number {
+        Some(expr) => CellValue::Expr(expr),
+        None => CellValue::Str(field.to_string()),
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct CellPosition {
     pub row: u32,
     pub col: u32,
@@ -74,20 +134,34 @@ impl fmt::Display for CellPosition {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct CellArray {
     from: CellRef,
     to: CellRef,
 }
 
 impl CellArray {
-    pub fn new(from: CellRef, to: CellRef) -> Self {
-        if from.pos.col != to.pos.col && from.pos.row != to.pos.row {
-            panic!("CellArray: either 'from' or 'to' must have same value");
+    // from must come before to, or be the same cell
+    pub fn new(from: CellRef, to: CellRef) -> Result<Self, FormulaError> {
+        let (first, last) = (&from.pos, &to.pos);
+        if (first.col != last.col && first.row != last.row) || first.col > last.col || first.row > last.row {
+            return Err(FormulaError::InvalidCellArray { from, to });
         }
-        CellArray { from, to }
+        Ok(CellArray { from, to })
+    }
+
+    // first cell of the array
+    pub fn start(&self) -> &CellRef {
+        &self.from
+    }
+
+    // last cell of the array
+    pub fn end(&self) -> &CellRef {
+        &self.to
     }
 
+    // a CellArray always spans at least one cell, so there is no is_empty
+    #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> u32 {
         if self.from.pos.col != self.to.pos.col {
             self.to.pos.col - self.from.pos.col + 1
@@ -95,13 +169,27 @@ impl CellArray {
             self.to.pos.row - self.from.pos.row + 1
         }
     }
+
+    // returns the position of every cell in the array, from 'from' to 'to'
+    pub fn positions(&self) -> Vec<CellPosition> {
+        let (from, to) = (&self.from.pos, &self.to.pos);
+        if from.col != to.col {
+            (from.col..=to.col)
+                .map(|col| CellPosition { row: from.row, col })
+                .collect()
+        } else {
+            (from.row..=to.row)
+                .map(|row| CellPosition { row, col: from.col })
+                .collect()
+        }
+    }
 }
 
 impl fmt::Display for CellArray {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut res = String::new();
         res.push_str(&self.from.to_string());
-        res.push_str(":");
+        res.push(':');
         res.push_str(&self.to.to_string());
         write!(f, "{}", res)
     }
@@ -114,22 +202,29 @@ pub enum CellValue {
     Empty,
 }
 
-impl fmt::Display for CellValue {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+impl CellValue {
+    // returns the content of the cell as written in a csv of dialect
+    pub fn format_with(&self, dialect: &CsvDialect) -> String {
         let mut res = String::new();
         match &self {
             CellValue::Str(str) => res.push_str(str),
             CellValue::Empty => (),
             CellValue::Expr(expr) => {
-                res.push_str("=");
-                res.push_str(&expr.to_string())
+                res.push('=');
+                res.push_str(&expr.format_with(dialect))
             }
         }
-        write!(f, "{}", res)
+        res
+    }
+}
+
+impl fmt::Display for CellValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.format_with(&CsvDialect::default()))
     }
 }
 
-#[derive(Debug,Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct CellRef {
     pub pos: CellPosition,
     pub fix_row: bool,
@@ -151,7 +246,46 @@ impl fmt::Display for CellRef {
     }
 }
 
-#[derive(Debug, Clone)]
+// Parses an A1 style reference such as "AB12" or "$AB$12"
+impl FromStr for CellRef {
+    type Err = FormulaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = |position: usize, message: &str| FormulaError::Parse {
+            input: s.to_string(),
+            position,
+            message: message.to_string(),
+        };
+        let mut rest = s;
+        let fix_col = rest.starts_with('$');
+        if fix_col {
+            rest = &rest[1..];
+        }
+        let col_len = rest.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(rest.len());
+        let col = from_csv_col(&rest[..col_len])
+            .ok_or_else(|| error(s.len() - rest.len(), "expected a column label"))?;
+        rest = &rest[col_len..];
+        let fix_row = rest.starts_with('$');
+        if fix_row {
+            rest = &rest[1..];
+        }
+        let row_start = s.len() - rest.len();
+        if let Some(i) = rest.find(|c: char| !c.is_ascii_digit()) {
+            return Err(error(row_start + i, "unexpected character in row number"));
+        }
+        let row: u32 = match rest.parse::<u32>() {
+            Ok(row) if row > 0 => row - 1,
+            _ => return Err(error(row_start, "expected a row number from 1")),
+        };
+        Ok(CellRef {
+            pos: CellPosition { row, col },
+            fix_row,
+            fix_col,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum CellExpr {
     BinaryOp(BinOp, Box<CellExpr>, Box<CellExpr>),
     Ref(CellRef),
@@ -161,15 +295,25 @@ pub enum CellExpr {
     Percentage(Decimal), // 10% == 0.1
 }
 
-impl fmt::Display for CellExpr {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+impl CellExpr {
+    // returns a SumProduct of left and right if both have the same length
+    pub fn sum_product(left: CellArray, right: CellArray) -> Result<Self, FormulaError> {
+        if left.len() != right.len() {
+            return Err(FormulaError::MismatchedArrays { left, right });
+        }
+        Ok(CellExpr::SumProduct(left, right))
+    }
+
+    // returns the formula, without the leading '=', as written in a csv of
+    // dialect
+    pub fn format_with(&self, dialect: &CsvDialect) -> String {
         let mut res = String::new();
         match self {
             CellExpr::BinaryOp(op, left, right) => {
                 res.push('(');
-                res.push_str(&left.as_ref().to_string());
+                res.push_str(&left.format_with(dialect));
                 res.push_str(&op.to_string());
-                res.push_str(&right.as_ref().to_string());
+                res.push_str(&right.format_with(dialect));
                 res.push(')');
             }
             CellExpr::Ref(cell_ref) => {
@@ -181,27 +325,35 @@ impl fmt::Display for CellExpr {
                 res.push(')');
             }
             CellExpr::SumProduct(left, right) => {
-                if left.len() != right.len() {
-                    panic!("SumProduct: CellArrays cannot be if different length.");
-                }
                 res.push_str("SUMPRODUCT(");
                 res.push_str(&left.to_string());
-                res.push(',');
+                res.push(dialect.argument_separator);
                 res.push_str(&right.to_string());
                 res.push(')');
             }
-            CellExpr::Number(x) => res.push_str(&x.round_dp(3).to_string()),
+            CellExpr::Number(x) => res.push_str(&format_decimal(dialect.precision.round(*x), dialect)),
             CellExpr::Percentage(val) => {
-                let p = val * dec!(100);
-                res.push_str(&p.round_dp(3).to_string());
+                let p = dialect.precision.round(val * dec!(100));
+                res.push_str(&format_decimal(p, dialect));
                 res.push('%');
             }
         }
-        write!(f, "{}", res)
+        res
     }
 }
 
-#[derive(Debug, Clone)]
+impl fmt::Display for CellExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.format_with(&CsvDialect::default()))
+    }
+}
+
+// returns x with the decimal separator of dialect
+fn format_decimal(x: Decimal, dialect: &CsvDialect) -> String {
+    x.to_string().replace('.', &dialect.decimal_separator.to_string())
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum BinOp {
     Add,
     Sub,
@@ -222,18 +374,41 @@ impl fmt::Display for BinOp {
     }
 }
 
-// takes row, col as u32 and return cell label
-// takes u32 and returns the column label
-// ex: 4 -> D
-// ex: (4 + 26) -> DD
+// takes u32 and returns the column label, columns are numbered from 0
+// in bijective base 26
+// ex: 3 -> D
+// ex: 26 -> AA
+// ex: 27 -> AB
 fn to_csv_col(col: u32) -> String {
-    let c: char = (b'A' + ((col % 26) as u8)) as char;
-    let n = (col / 26) + 1;
-    let mut col_label = String::new();
-    for _ in 0..n {
-        col_label.push(c);
+    let mut n = col as u64 + 1;
+    let mut col_label: Vec<char> = Vec::new();
+    while n > 0 {
+        n -= 1;
+        col_label.push((b'A' + (n % 26) as u8) as char);
+        n /= 26;
     }
-    col_label
+    col_label.iter().rev().collect()
+}
+
+// takes a column label and returns the column, the inverse of to_csv_col.
+// Returns None if label is empty, holds a non letter or is too large
+// ex: AB -> 27
+fn from_csv_col(label: &str) -> Option<u32> {
+    if label.is_empty() {
+        return None;
+    }
+    let mut n: u64 = 0;
+    for c in label.chars() {
+        if !c.is_ascii_alphabetic() {
+            return None;
+        }
+        let digit = (c.to_ascii_uppercase() as u8 - b'A') as u64 + 1;
+        n = n * 26 + digit;
+        if n > u32::MAX as u64 + 1 {
+            return None;
+        }
+    }
+    Some((n - 1) as u32)
 }
 
 #[cfg(test)]
@@ -264,34 +439,176 @@ mod tests {
 
     #[test]
     fn test_cell_position() {
-        assert_eq!(CP_1.to_string(), String::from("$DD101"));
+        assert_eq!(CP_1.to_string(), String::from("$AD101"));
+    }
+
+    #[test]
+    fn test_csv_col() {
+        assert_eq!(to_csv_col(0), "A");
+        assert_eq!(to_csv_col(25), "Z");
+        assert_eq!(to_csv_col(26), "AA");
+        assert_eq!(to_csv_col(27), "AB");
+        assert_eq!(to_csv_col(701), "ZZ");
+        assert_eq!(to_csv_col(702), "AAA");
+        assert_eq!(to_csv_col(16383), "XFD");
+        assert_eq!(from_csv_col("xfd"), Some(16383));
+        assert_eq!(from_csv_col(""), None);
+        assert_eq!(from_csv_col("A1"), None);
+    }
+
+    #[test]
+    fn test_csv_col_round_trip() {
+        let mut prev = String::new();
+        for col in (0..100_000).chain(u32::MAX - 100..=u32::MAX) {
+            let label = to_csv_col(col);
+            assert_eq!(from_csv_col(&label), Some(col));
+            // labels are ordered by length, then alphabetically
+            assert!((prev.len(), &prev) < (label.len(), &label));
+            prev = label;
+        }
+        assert_eq!(from_csv_col(&to_csv_col(u32::MAX)), Some(u32::MAX));
+        assert_eq!(from_csv_col("ZZZZZZZZ"), None);
+    }
+
+    #[test]
+    fn test_cell_ref_round_trip() {
+        for (row, col) in [(0, 0), (11, 27), (99, 701), (1_048_575, 16383), (u32::MAX - 1, u32::MAX)] {
+            for (fix_row, fix_col) in [(false, false), (true, false), (false, true), (true, true)] {
+                let cell_ref = CellRef { pos: CellPosition { row, col }, fix_row, fix_col };
+                let parsed: CellRef = cell_ref.to_string().parse().unwrap();
+                assert_eq!(parsed.pos, cell_ref.pos);
+                assert_eq!((parsed.fix_row, parsed.fix_col), (fix_row, fix_col));
+            }
+        }
+        let parsed: CellRef = "$AB$12".parse().unwrap();
+        assert_eq!(parsed.pos, CellPosition { row: 11, col: 27 });
+    }
+
+    #[test]
+    fn test_cell_ref_invalid() {
+        for (input, position) in [("", 0), ("$", 1), ("12", 0), ("A", 1), ("A0", 1), ("AB1x", 3), ("A$$1", 2)] {
+            match input.parse::<CellRef>() {
+                Err(FormulaError::Parse { position: p, .. }) => assert_eq!(p, position, "{}", input),
+                _ => panic!("expected parse error for {}", input),
+            }
+        }
     }
 
     #[test]
     fn test_cell_array() {
-        let ca = CellArray::new(CP_1, CP_2);
-        assert_eq!(ca.to_string(), String::from("$DD101:$DD201"));
+        let ca = CellArray::new(CP_1, CP_2).unwrap();
+        assert_eq!(ca.to_string(), String::from("$AD101:$AD201"));
     }
 
     #[test]
-    #[should_panic]
-    fn test_cell_array_panic() {
-        CellArray::new(CP_1, CP_4);
+    fn test_cell_array_invalid() {
+        let res = CellArray::new(CP_1, CP_4);
+        assert!(matches!(res, Err(FormulaError::InvalidCellArray { .. })));
+        let res = CellArray::new(CP_2, CP_1);
+        assert!(matches!(res, Err(FormulaError::InvalidCellArray { .. })));
     }
 
     #[test]
     fn test_sum() {
-        let cv: CellValue = CellValue::Expr(CellExpr::Sum(CellArray::new(CP_1, CP_2)));
-        assert_eq!(cv.to_string(), "=SUM($DD101:$DD201)")
+        let cv: CellValue = CellValue::Expr(CellExpr::Sum(CellArray::new(CP_1, CP_2).unwrap()));
+        assert_eq!(cv.to_string(), "=SUM($AD101:$AD201)")
     }
 
     #[test]
     fn test_sumproduct() {
-        let cv: CellValue = CellValue::Expr(CellExpr::SumProduct(
-            CellArray::new(CP_1, CP_2),
-            CellArray::new(CP_3, CP_4),
-        ));
-        assert_eq!(cv.to_string(), "=SUMPRODUCT($DD101:$DD201,$NN101:$NN201)");
+        let cv: CellValue = CellValue::Expr(CellExpr::sum_product(
+            CellArray::new(CP_1, CP_2).unwrap(),
+            CellArray::new(CP_3, CP_4).unwrap(),
+        ).unwrap());
+        assert_eq!(cv.to_string(), "=SUMPRODUCT($AD101:$AD201,$AN101:$AN201)");
+    }
+
+    #[test]
+    fn test_sumproduct_mismatched() {
+        let res = CellExpr::sum_product(
+            CellArray::new(CP_1, CP_2).unwrap(),
+            CellArray::new(CP_1, CP_3).unwrap(),
+        );
+        assert!(matches!(res, Err(FormulaError::MismatchedArrays { .. })));
+    }
+
+    #[test]
+    fn test_grid_quoting() {
+        let cells = vec![
+            CSVCell {
+                value: CellValue::Str(String::from("flour, type \"00\"")),
+                position: CellPosition { row: 0, col: 0 },
+            },
+            CSVCell {
+                value: CellValue::Expr(CellExpr::sum_product(
+                    CellArray::new(CP_1, CP_2).unwrap(),
+                    CellArray::new(CP_3, CP_4).unwrap(),
+                ).unwrap()),
+                position: CellPosition { row: 0, col: 1 },
+            },
+        ];
+        assert_eq!(
+            csv_cells_to_grid(&cells).unwrap(),
+            "\"flour, type \"\"00\"\"\",\"=SUMPRODUCT($AD101:$AD201,$AN101:$AN201)\"\r\n"
+        );
+    }
+
+    #[test]
+    fn test_grid_round_trip() {
+        let csv = "\"flour, type \"\"00\"\"\",=SUM($AD101:$AD201)\r\n,12.5%,500\n\n\"a\nb\"\n";
+        let cells = csv_grid_to_cells(csv).unwrap();
+        assert_eq!(cells.len(), 5);
+        assert!(matches!(&cells[0].value, CellValue::Str(s) if s == "flour, type \"00\""));
+        assert!(matches!(&cells[1].value, CellValue::Expr(CellExpr::Sum(_))));
+        assert!(matches!(&cells[2].value, CellValue::Expr(CellExpr::Percentage(x)) if *x == dec!(0.125)));
+        assert!(matches!(&cells[3].value, CellValue::Expr(CellExpr::Number(x)) if *x == dec!(500)));
+        assert_eq!(cells[4].position, CellPosition { row: 3, col: 0 });
+        assert_eq!(
+            csv_cells_to_grid(&cells).unwrap(),
+            "\"flour, type \"\"00\"\"\",=SUM($AD101:$AD201),\r\n,=12.5%,=500\r\n,,\r\n\"a\nb\",,\r\n"
+        );
+
+        let res = csv_grid_to_cells("a,\"b\n");
+        assert!(matches!(res, Err(FormulaError::Parse { position: 2, .. })));
+    }
+
+    #[test]
+    fn test_grid_dialect() {
+        let dialect = CsvDialect {
+            line_terminator: String::from("\n"),
+            ..CsvDialect::semicolon()
+        };
+        let cells = vec![
+            CSVCell {
+                value: CellValue::Str(String::from("flour, type 00")),
+                position: CellPosition { row: 0, col: 0 },
+            },
+            CSVCell {
+                value: CellValue::Expr(CellExpr::BinaryOp(
+                    BinOp::Mult,
+                    Box::new(CellExpr::sum_product(
+                        CellArray::new(CP_1, CP_2).unwrap(),
+                        CellArray::new(CP_3, CP_4).unwrap(),
+                    ).unwrap()),
+                    Box::new(CellExpr::Number(dec!(2.5))),
+                )),
+                position: CellPosition { row: 0, col: 1 },
+            },
+        ];
+        let csv = csv_cells_to_grid_with_dialect(&cells, &dialect).unwrap();
+        assert_eq!(csv, "flour, type 00;\"=(SUMPRODUCT($AD101:$AD201;$AN101:$AN201)*2,5)\"\n");
+        let parsed = csv_grid_to_cells_with_dialect(&csv, &dialect).unwrap();
+        assert_eq!(csv_cells_to_grid_with_dialect(&parsed, &dialect).unwrap(), csv);
+    }
+
+    #[test]
+    fn test_overlapping_cells() {
+        let cell = CSVCell {
+            value: CellValue::Str(String::from("flour")),
+            position: CP_1.pos,
+        };
+        let res = csv_cells_to_grid(&[cell.clone(), cell]);
+        assert!(matches!(res, Err(FormulaError::OverlappingCells { .. })));
     }
 
     #[test]
@@ -299,13 +616,15 @@ mod tests {
         let e1 = CellExpr::Number(dec!(3));
         let e2: CellExpr = CellExpr::Percentage(dec!(0.1234567));
         let e3 = CellExpr::BinaryOp(BinOp::Mult, Box::new(e1), Box::new(e2));
-        assert_eq!(e3.to_string(), "(3*12.3456700%)");
-        let e4: CellExpr =
-            CellExpr::SumProduct(CellArray::new(CP_1, CP_2), CellArray::new(CP_3, CP_4));
+        assert_eq!(e3.to_string(), "(3*12.346%)");
+        let e4: CellExpr = CellExpr::SumProduct(
+            CellArray::new(CP_1, CP_2).unwrap(),
+            CellArray::new(CP_3, CP_4).unwrap(),
+        );
         let e5: CellExpr = CellExpr::BinaryOp(BinOp::Add, Box::new(e3), Box::new(e4));
         assert_eq!(
             e5.to_string(),
-            "((3*12.3456700%)+SUMPRODUCT($DD101:$DD201,$NN101:$NN201))"
+            "((3*12.346%)+SUMPRODUCT($AD101:$AD201,$AN101:$AN201))"
         );
     }
 }
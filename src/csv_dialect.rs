@@ -0,0 +1,295 @@
+use crate::error::FormulaError;
+use rust_decimal::{Decimal, RoundingStrategy};
+
+// How fields that need quoting are chosen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quoting {
+    // only fields holding the delimiter, the quote or a line break
+    Minimal,
+    // every non empty field
+    Always,
+}
+
+// How a quote inside a quoted field is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuoteEscape {
+    // the quote is doubled, as in RFC 4180
+    Doubled,
+    // the quote, and the escape itself, are preceded by the given character
+    Prefixed(char),
+}
+
+// How a number is rounded to the decimals of a Precision
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Rounding {
+    // to the nearest, halves to the even neighbour
+    #[default]
+    HalfEven,
+    // to the nearest, halves away from zero
+    HalfUp,
+    // to the nearest, halves towards zero
+    HalfDown,
+    // towards zero
+    Down,
+    // away from zero
+    Up,
+}
+
+impl Rounding {
+    fn strategy(&self) -> RoundingStrategy {
+        match self {
+            Rounding::HalfEven => RoundingStrategy::MidpointNearestEven,
+            Rounding::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+            Rounding::HalfDown => RoundingStrategy::MidpointTowardZero,
+            Rounding::Down => RoundingStrategy::ToZero,
+            Rounding::Up => RoundingStrategy::AwayFromZero,
+        }
+    }
+}
+
+// The number of decimals numbers are written with, 3 by default. Percentages
+// are rounded after they are multiplied by 100, so 0.123456 is 12.346%
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Precision {
+    pub decimals: u32,
+    pub rounding: Rounding,
+}
+
+impl Default for Precision {
+    fn default() -> Self {
+        Precision {
+            decimals: 3,
+            rounding: Rounding::default(),
+        }
+    }
+}
+
+impl Precision {
+    // returns x rounded, without trailing zeros
+    pub fn round(&self, x: Decimal) -> Decimal {
+        x.round_dp_with_strategy(self.decimals, self.rounding.strategy())
+            .normalize()
+    }
+}
+
+// The flavour of csv to read and write. The default follows RFC 4180, with
+// '.' as the decimal separator and ',' between function arguments. Many
+// European spreadsheet applications expect the semicolon dialect instead
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsvDialect {
+    pub delimiter: char,
+    pub quote: char,
+    pub quoting: Quoting,
+    pub escape: QuoteEscape,
+    pub line_terminator: String,
+    // separates the integer and fractional part of numbers in formulas
+    pub decimal_separator: char,
+    // separates the arguments of functions, as in SUMPRODUCT(a;b)
+    pub argument_separator: char,
+    // how numbers and percentages in formulas are rounded
+    pub precision: Precision,
+}
+
+impl Default for CsvDialect {
+    fn default() -> Self {
+        CsvDialect {
+            delimiter: ',',
+            quote: '"',
+            quoting: Quoting::Minimal,
+            escape: QuoteEscape::Doubled,
+            line_terminator: String::from("\r\n"),
+            decimal_separator: '.',
+            argument_separator: ',',
+            precision: Precision::default(),
+        }
+    }
+}
+
+impl CsvDialect {
+    // ';' between fields and function arguments and ',' as the decimal
+    // separator
+    pub fn semicolon() -> Self {
+        CsvDialect {
+            delimiter: ';',
+            decimal_separator: ',',
+            argument_separator: ';',
+            ..CsvDialect::default()
+        }
+    }
+
+    // Returns a FormulaError::InvalidDialect if the separators and quote
+    // can not be told apart
+    pub fn validate(&self) -> Result<(), FormulaError> {
+        let invalid = |message: &str| {
+            Err(FormulaError::InvalidDialect {
+                message: message.to_string(),
+            })
+        };
+        if self.delimiter == self.quote {
+            return invalid("delimiter and quote must differ");
+        }
+        if self.decimal_separator == self.argument_separator {
+            return invalid("decimal separator and argument separator must differ");
+        }
+        if self.delimiter == self.decimal_separator {
+            return invalid("delimiter and decimal separator must differ");
+        }
+        if self.line_terminator.is_empty() {
+            return invalid("line terminator must not be empty");
+        }
+        let reserved = ['\r', '\n'];
+        if reserved.contains(&self.delimiter) || reserved.contains(&self.quote) {
+            return invalid("delimiter and quote must not be line breaks");
+        }
+        if let QuoteEscape::Prefixed(c) = self.escape {
+            if c == self.delimiter || reserved.contains(&c) {
+                return invalid("escape must differ from the delimiter and line breaks");
+            }
+        }
+        Ok(())
+    }
+
+    // returns field, quoted if the quoting rule asks for it
+    pub fn quote_field(&self, field: &str) -> String {
+        let needs_quotes = match self.quoting {
+            Quoting::Always => !field.is_empty(),
+            Quoting::Minimal => field.contains([self.delimiter, self.quote, '\n', '\r']),
+        };
+        if !needs_quotes {
+            return field.to_string();
+        }
+        let mut quoted = String::new();
+        quoted.push(self.quote);
+        for c in field.chars() {
+            match self.escape {
+                QuoteEscape::Doubled if c == self.quote => quoted.push(self.quote),
+                QuoteEscape::Prefixed(e) if c == self.quote || c == e => quoted.push(e),
+                _ => (),
+            }
+            quoted.push(c);
+        }
+        quoted.push(self.quote);
+        quoted
+    }
+
+    // splits csv into rows of fields, removing the quotes written by
+    // quote_field. Rows end with either "\n" or "\r\n"
+    pub fn split_rows(&self, csv: &str) -> Result<Vec<Vec<String>>, FormulaError> {
+        let mut rows: Vec<Vec<String>> = Vec::new();
+        let mut row: Vec<String> = Vec::new();
+        let mut field = String::new();
+        let mut line_start = 0;
+        let mut chars = csv.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                c if c == self.quote && field.is_empty() => loop {
+                    let next = chars.next().map(|(_, c)| c);
+                    match (next, self.escape) {
+                        (Some(c), QuoteEscape::Prefixed(e)) if c == e => match chars.next() {
+                            Some((_, escaped)) => field.push(escaped),
+                            None => field.push(e),
+                        },
+                        (Some(c), QuoteEscape::Doubled)
+                            if c == self.quote && matches!(chars.peek(), Some((_, q)) if *q == self.quote) =>
+                        {
+                            chars.next();
+                            field.push(self.quote);
+                        }
+                        (Some(c), _) if c == self.quote => break,
+                        (Some(c), _) => field.push(c),
+                        (None, _) => {
+                            return Err(FormulaError::Parse {
+                                input: csv[line_start..].lines().next().unwrap_or_default().to_string(),
+                                position: i - line_start,
+                                message: String::from("unterminated quoted field"),
+                            })
+                        }
+                    }
+                },
+                c if c == self.delimiter => row.push(std::mem::take(&mut field)),
+                '\r' if matches!(chars.peek(), Some((_, '\n'))) => (),
+                '\n' => {
+                    row.push(std::mem::take(&mut field));
+                    rows.push(std::mem::take(&mut row));
+                    line_start = i + 1;
+                }
+                c => field.push(c),
+            }
+        }
+        if !field.is_empty() || !row.is_empty() {
+            row.push(field);
+            rows.push(row);
+        }
+        Ok(rows)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal_macros::dec;
+
+    #[test]
+    fn test_quote_field() {
+        let rfc = CsvDialect::default();
+        assert_eq!(rfc.quote_field("water"), "water");
+        assert_eq!(rfc.quote_field("flour, type \"00\""), "\"flour, type \"\"00\"\"\"");
+        let semicolon = CsvDialect::semicolon();
+        assert_eq!(semicolon.quote_field("flour, type 00"), "flour, type 00");
+        assert_eq!(semicolon.quote_field("a;b"), "\"a;b\"");
+        let always = CsvDialect {
+            quoting: Quoting::Always,
+            escape: QuoteEscape::Prefixed('\\'),
+            ..CsvDialect::default()
+        };
+        assert_eq!(always.quote_field("say \"hi\" \\"), "\"say \\\"hi\\\" \\\\\"");
+        assert_eq!(always.quote_field(""), "");
+    }
+
+    #[test]
+    fn test_split_rows() {
+        let always = CsvDialect {
+            quoting: Quoting::Always,
+            escape: QuoteEscape::Prefixed('\\'),
+            ..CsvDialect::default()
+        };
+        let fields = ["say \"hi\" \\", "a,b", ""];
+        let line: Vec<String> = fields.iter().map(|f| always.quote_field(f)).collect();
+        let rows = always.split_rows(&line.join(",")).unwrap();
+        assert_eq!(rows, vec![fields.to_vec()]);
+
+        let rows = CsvDialect::semicolon().split_rows("a;\"b;c\"\r\n;1,5\n").unwrap();
+        assert_eq!(rows, vec![vec!["a", "b;c"], vec!["", "1,5"]]);
+    }
+
+    #[test]
+    fn test_precision() {
+        let precision = Precision::default();
+        assert_eq!(precision.round(dec!(12.3456700)), dec!(12.346));
+        assert_eq!(precision.round(dec!(70.00)).to_string(), "70");
+        assert_eq!(precision.round(dec!(0.0025)), dec!(0.002));
+        let half_up = Precision { decimals: 2, rounding: Rounding::HalfUp };
+        assert_eq!(half_up.round(dec!(0.125)), dec!(0.13));
+        let down = Precision { decimals: 1, rounding: Rounding::Down };
+        assert_eq!(down.round(dec!(-1.99)), dec!(-1.9));
+        let up = Precision { decimals: 0, rounding: Rounding::Up };
+        assert_eq!(up.round(dec!(1.01)), dec!(2));
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(CsvDialect::default().validate().is_ok());
+        assert!(CsvDialect::semicolon().validate().is_ok());
+        // a semicolon delimiter keeps the comma argument separator apart
+        let semicolon_delimited = CsvDialect {
+            delimiter: ';',
+            ..CsvDialect::default()
+        };
+        assert!(semicolon_delimited.validate().is_ok());
+        let clash = CsvDialect {
+            decimal_separator: ',',
+            ..CsvDialect::default()
+        };
+        assert!(matches!(clash.validate(), Err(FormulaError::InvalidDialect { .. })));
+    }
+}
@@ -1,29 +1,128 @@
-extern crate yaml_rust;
-use crate::csv_cell::{ BinOp, CSVCell, CellArray, CellExpr, CellPosition, CellValue, csv_cells_to_grid, CellRef};
-use rust_decimal::{prelude::FromPrimitive, Decimal};
+use crate::category::{self, Category};
+use crate::csv_cell::{
+    csv_cells_to_grid_with_dialect, csv_grid_to_cells_with_dialect, BinOp, CSVCell, CellArray, CellExpr, CellPosition, CellRef,
+    CellValue,
+};
+use crate::csv_dialect::{CsvDialect, Precision};
+use crate::error::{FormulaError, Location};
+use crate::eval::{Evaluator, Value};
+use crate::layout::{Input, LayoutKind, SheetLayout};
+use crate::schema::{
+    invalid_field, is_loss, is_positive, ComponentSpec, FormulaSpec, IngredientSpec, CATEGORY_EXPECTED,
+    FLOUR_CATEGORY_EXPECTED, LOSS_EXPECTED, POSITIVE_EXPECTED, SCHEMA_VERSION,
+};
+use crate::ods::cells_to_ods;
+use crate::unit::Unit;
+use crate::xlsx::cells_to_xlsx;
+use rust_decimal::Decimal;
 use rust_decimal_macros::dec;
 use std::{
     collections::{HashMap, HashSet, VecDeque},
-    fs,
+    fmt, fs, io,
 };
-use yaml_rust::YamlLoader;
 
-const ROW_OFFSET: usize = 2;
-const COL_OFFSET: usize = 1;
 const MIX: &str = "mix";
+// text of the cells marking flour rows, after the overall formula
+const FLOUR_MARKER: &str = "flour";
+// header of the overall formula
+const OVERALL: &str = "overall";
+// title of the percent columns and the label of the totals. The mass
+// columns are titled by the unit of the masses
+const PERCENT_TITLE: &str = "%";
+const TOTAL_LABEL: &str = "total";
+// label of the share of every component
+const SHARE_LABEL: &str = "component share";
+// follows the category in the label of its subtotal
+const SUBTOTAL_LABEL: &str = "subtotal";
+// title of the water fractions and what follows a name in the label of its
+// hydration
+const WATER_TITLE: &str = "water content";
+const HYDRATION_LABEL: &str = "hydration";
+// title of the categories
+const CATEGORY_TITLE: &str = "category";
+// follows the name of a preferment in the label of its prefermented flour
+// and that of a seed in the label of its inoculation
+const PREFERMENTED_FLOUR_LABEL: &str = "prefermented flour";
+const INOCULATION_LABEL: &str = "inoculation";
 
-#[derive(Debug)]
-enum Ingredient {
+// An ingredient of a component with its baker's percentage, relative to
+// the flour of the component, as a fraction (0.7 for 70%)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ingredient {
     Flour(Decimal),
     NonFlour(Decimal),
 }
 
+impl Ingredient {
+    pub fn percentage(&self) -> Decimal {
+        match self {
+            Ingredient::Flour(x) | Ingredient::NonFlour(x) => *x,
+        }
+    }
+
+    pub fn is_flour(&self) -> bool {
+        matches!(self, Ingredient::Flour(_))
+    }
+}
+
+// How the ingredients of the generated sheet are ordered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IngredientOrder {
+    // in the order they first appear in the source, component by component
+    #[default]
+    Source,
+    // by their mass in the dough, heaviest first
+    Mass,
+    // flour first, then the other ingredients, each in source order
+    FlourFirst,
+    // grouped by category in the order of Category, components last, each
+    // group in source order
+    Category,
+}
+
 // Ingredient name's may reference other components.
 // All components but "mix" must be referenced by another segment
 // "mix" may not be referenced
 #[derive(Debug)]
-struct DoughComponent {
+pub struct DoughComponent {
+    name: String,
     ingredients: HashMap<String, Ingredient>,
+    // names of the ingredients in source order
+    ingredient_order: Vec<String>,
+    // notes on ingredients, kept to be written out again
+    notes: HashMap<String, String>,
+    location: Location,
+}
+
+impl DoughComponent {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // where the component starts in the file it was read from
+    pub fn location(&self) -> Location {
+        self.location
+    }
+
+    // the ingredients with their names, in source order
+    pub fn ingredients(&self) -> impl Iterator<Item = (&str, &Ingredient)> {
+        self.ingredient_order
+            .iter()
+            .map(|ing_name| (ing_name.as_str(), &self.ingredients[ing_name]))
+    }
+
+    pub fn ingredient(&self, ing_name: &str) -> Option<&Ingredient> {
+        self.ingredients.get(ing_name)
+    }
+
+    pub fn notes(&self, ing_name: &str) -> Option<&str> {
+        self.notes.get(ing_name).map(String::as_str)
+    }
+
+    // sum of the percentages of all ingredients
+    pub fn total(&self) -> Decimal {
+        self.ingredients.values().map(Ingredient::percentage).sum()
+    }
 }
 
 #[derive(Debug)]
@@ -32,265 +131,1556 @@ pub struct DoughFormula {
     components: HashMap<String, DoughComponent>,
     flour: HashSet<String>,
     non_flour: HashSet<String>,
+    // category of every ingredient that is not a component
+    categories: HashMap<String, Category>,
+    // fraction of every ingredient that is not a component that is water
+    water_fractions: HashMap<String, Decimal>,
+    component_order: Vec<String>,
+    ingredient_order: Vec<String>,
+    dough_serving: Option<Decimal>,
+    batch_size: Decimal,
+    loss: Decimal,
+    layout: LayoutKind,
+    mass_unit: Unit,
+}
+
+impl DoughFormula {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // Returns the components in topological order: every component comes
+    // after the components it holds. Components are visited in source order
+    // and their parts in ingredient order
+    pub fn components(&self) -> impl Iterator<Item = &DoughComponent> {
+        let mut order: Vec<&str> = Vec::new();
+        for comp_name in &self.component_order {
+            self.push_after_parts(comp_name, &mut order);
+        }
+        order.into_iter().map(|comp_name| &self.components[comp_name])
+    }
+
+    // pushes the components held by comp_name and then comp_name itself to
+    // order, skipping those already in it. The graph has been checked for
+    // cycles
+    fn push_after_parts<'a>(&'a self, comp_name: &'a str, order: &mut Vec<&'a str>) {
+        if order.contains(&comp_name) {
+            return;
+        }
+        for ing_name in &self.components[comp_name].ingredient_order {
+            if self.components.contains_key(ing_name) {
+                self.push_after_parts(ing_name, order);
+            }
+        }
+        order.push(comp_name);
+    }
+
+    pub fn component(&self, comp_name: &str) -> Option<&DoughComponent> {
+        self.components.get(comp_name)
+    }
+
+    // the names of the ingredients of every component, including the
+    // components used as ingredients, in the order of the generated sheet
+    pub fn ingredient_names(&self) -> &[String] {
+        &self.ingredient_order
+    }
+
+    pub fn is_flour(&self, ing_name: &str) -> bool {
+        self.flour.contains(ing_name)
+    }
+
+    // the category of ing_name, None for components and unknown names
+    pub fn category(&self, ing_name: &str) -> Option<Category> {
+        self.categories.get(ing_name).copied()
+    }
+
+    // the fraction of ing_name that is water, None for components and
+    // unknown names
+    pub fn water_fraction(&self, ing_name: &str) -> Option<Decimal> {
+        self.water_fractions.get(ing_name).copied()
+    }
+
+    // Returns the water of the ingredients of comp_name as a fraction of its
+    // flour. Components among its ingredients count as dry, so the
+    // hydration of mix leaves out the water of its preferments
+    pub fn component_hydration(&self, comp_name: &str) -> Option<Decimal> {
+        let comp = self.components.get(comp_name)?;
+        let water: Decimal = comp
+            .ingredients
+            .iter()
+            .filter_map(|(ing_name, ing)| self.water_fraction(ing_name).map(|w| w * ing.percentage()))
+            .sum();
+        let flour: Decimal = comp
+            .ingredients
+            .iter()
+            .filter(|(ing_name, _)| self.flour.contains(*ing_name))
+            .map(|(_, ing)| ing.percentage())
+            .sum();
+        Some(water / flour)
+    }
+
+    // Returns the water of the whole dough as a fraction of its flour,
+    // counting the water of every component
+    pub fn hydration(&self) -> Decimal {
+        let masses = self.ingredient_masses();
+        let water: Decimal = masses
+            .iter()
+            .filter_map(|(ing_name, mass)| self.water_fraction(ing_name).map(|w| w * mass))
+            .sum();
+        let flour: Decimal = masses
+            .iter()
+            .filter(|(ing_name, _)| self.flour.contains(*ing_name))
+            .map(|(_, mass)| mass)
+            .sum();
+        water / flour
+    }
+
+    // Returns the flour of comp_name as a fraction of the flour of the whole
+    // dough. The flour of the preferments it holds is counted in theirs, so
+    // that the prefermented flour of the preferments adds up. None for mix,
+    // which is not a preferment, and unknown names
+    pub fn prefermented_flour(&self, comp_name: &str) -> Option<Decimal> {
+        if comp_name == MIX || !self.components.contains_key(comp_name) {
+            return None;
+        }
+        let flour = self.component_flour();
+        let total: Decimal = flour.values().sum();
+        Some(flour[comp_name] / total)
+    }
+
+    // Returns the flour of every preferment as a fraction of the flour of the
+    // whole dough
+    pub fn total_prefermented_flour(&self) -> Decimal {
+        let flour = self.component_flour();
+        let total: Decimal = flour.values().sum();
+        (total - flour[MIX]) / total
+    }
+
+    // Returns the inoculation of parent by seed, the baker's percentage of
+    // seed in parent, which is already relative to the flour of parent. None
+    // if seed is not a component used in parent
+    pub fn inoculation(&self, seed: &str, parent: &str) -> Option<Decimal> {
+        if !self.components.contains_key(seed) {
+            return None;
+        }
+        self.components.get(parent)?.ingredients.get(seed).map(|ing| ing.percentage())
+    }
+
+    // Returns every seed with the component it is used in, by component and
+    // then ingredient order. A component used in several others is a seed
+    // of each one
+    pub fn seeds(&self) -> Vec<(&str, &str)> {
+        let mut seeds: Vec<(&str, &str)> = Vec::new();
+        for parent in &self.component_order {
+            let comp = &self.components[parent];
+            for ing_name in &self.ingredient_order {
+                if self.components.contains_key(ing_name) && comp.ingredients.contains_key(ing_name) {
+                    seeds.push((ing_name, parent));
+                }
+            }
+        }
+        seeds
+    }
+
+    // whether ing_name is a component used as an ingredient of others
+    pub fn is_component(&self, ing_name: &str) -> bool {
+        self.components.contains_key(ing_name)
+    }
+
+    pub fn dough_serving(&self) -> Option<Decimal> {
+        self.dough_serving
+    }
+
+    pub fn batch_size(&self) -> Decimal {
+        self.batch_size
+    }
+
+    pub fn loss(&self) -> Decimal {
+        self.loss
+    }
+
+    pub fn layout(&self) -> LayoutKind {
+        self.layout
+    }
+
+    pub fn mass_unit(&self) -> Unit {
+        self.mass_unit
+    }
+
+    // index of comp_name in the component order
+    fn component_index(&self, comp_name: &str) -> usize {
+        self.component_order
+            .iter()
+            .position(|c| c == comp_name)
+            .expect("component is part of the formula")
+    }
+
+    // index of ing_name in the ingredient order
+    fn ingredient_index(&self, ing_name: &str) -> usize {
+        self.ingredient_order
+            .iter()
+            .position(|i| i == ing_name)
+            .expect("ingredient is part of the formula")
+    }
+
+    // Sets the mass of a single serving of dough. When set, the generated
+    // sheet contains input cells for the serving, batch size and loss and
+    // the mass column of every component is filled. The mass must be
+    // positive
+    pub fn set_dough_serving(&mut self, mass: Decimal) -> Result<(), FormulaError> {
+        check_setting("dough_serving", mass, POSITIVE_EXPECTED, is_positive)?;
+        self.dough_serving = Some(mass);
+        Ok(())
+    }
+
+    // Sets the number of servings made at once, which must be positive
+    pub fn set_batch_size(&mut self, batch_size: Decimal) -> Result<(), FormulaError> {
+        check_setting("batch_size", batch_size, POSITIVE_EXPECTED, is_positive)?;
+        self.batch_size = batch_size;
+        Ok(())
+    }
+
+    // Sets the fraction of dough lost while mixing, dividing and shaping,
+    // from 0 up to but excluding 1. The dough mass is raised so that the
+    // batch is still made after loss
+    pub fn set_loss(&mut self, loss: Decimal) -> Result<(), FormulaError> {
+        check_setting("loss", loss, LOSS_EXPECTED, is_loss)?;
+        self.loss = loss;
+        Ok(())
+    }
+
+    // Returns the mass of dough to mix, which is
+    // dough_serving * batch_size / (1 - loss)
+    pub fn dough_mass(&self) -> Option<Decimal> {
+        self.dough_serving
+            .map(|serving| serving * self.batch_size / (dec!(1) - self.loss))
+    }
+
+    // Sorts the ingredients of the generated sheet. Components are always in
+    // source order
+    pub fn sort_ingredients(&mut self, order: IngredientOrder) {
+        let mut source: Vec<String> = Vec::new();
+        for comp_name in &self.component_order {
+            for ing_name in &self.components[comp_name].ingredient_order {
+                if !source.contains(ing_name) {
+                    source.push(ing_name.clone());
+                }
+            }
+        }
+        match order {
+            IngredientOrder::Source => (),
+            IngredientOrder::Mass => {
+                let masses = self.ingredient_masses();
+                // stable, ties stay in source order
+                source.sort_by(|a, b| masses[b].cmp(&masses[a]));
+            }
+            IngredientOrder::FlourFirst => source.sort_by_key(|ing_name| !self.flour.contains(ing_name)),
+            IngredientOrder::Category => {
+                source.sort_by_key(|ing_name| (self.is_component(ing_name), self.category(ing_name)))
+            }
+        }
+        self.ingredient_order = source;
+    }
+
+    // returns the mass of every ingredient, components included, as a
+    // fraction of the total mass of mix
+    fn ingredient_masses(&self) -> HashMap<String, Decimal> {
+        let mut shares: HashMap<String, Decimal> = HashMap::new();
+        self.add_share(MIX, dec!(1), &mut shares);
+        let mut masses: HashMap<String, Decimal> = HashMap::new();
+        for (comp_name, share) in &shares {
+            let comp = &self.components[comp_name];
+            let total = comp.total();
+            for (ing_name, ing) in &comp.ingredients {
+                *masses.entry(ing_name.clone()).or_default() += share * ing.percentage() / total;
+            }
+        }
+        masses
+    }
+
+    // returns the flour of the ingredients of every component, leaving out
+    // that of the components it holds, as a fraction of the total mass of mix
+    fn component_flour(&self) -> HashMap<String, Decimal> {
+        let mut shares: HashMap<String, Decimal> = HashMap::new();
+        self.add_share(MIX, dec!(1), &mut shares);
+        shares
+            .into_iter()
+            .map(|(comp_name, share)| {
+                let comp = &self.components[&comp_name];
+                let flour: Decimal = comp
+                    .ingredients
+                    .iter()
+                    .filter(|(ing_name, _)| self.flour.contains(*ing_name))
+                    .map(|(_, ing)| ing.percentage())
+                    .sum();
+                let flour = share * flour / comp.total();
+                (comp_name, flour)
+            })
+            .collect()
+    }
+
+    // adds share to the share of comp_name and of the components it holds,
+    // following every path from mix. The graph has been checked for cycles
+    fn add_share(&self, comp_name: &str, share: Decimal, shares: &mut HashMap<String, Decimal>) {
+        *shares.entry(comp_name.to_string()).or_default() += share;
+        let comp = &self.components[comp_name];
+        let total = comp.total();
+        for ing_name in &comp.ingredient_order {
+            if self.components.contains_key(ing_name) {
+                let ing_share = share * comp.ingredients[ing_name].percentage() / total;
+                self.add_share(ing_name, ing_share, shares);
+            }
+        }
+    }
+
+    // Sets the unit of the mass columns of the generated sheet, grams by
+    // default. The dough serving is still given in grams
+    pub fn set_mass_unit(&mut self, unit: Unit) -> Result<(), FormulaError> {
+        if !unit.is_mass() {
+            return Err(FormulaError::VolumeUnit { unit });
+        }
+        self.mass_unit = unit;
+        Ok(())
+    }
+
+    // Sets how the blocks of the generated sheet are arranged
+    pub fn set_layout(&mut self, layout: LayoutKind) {
+        self.layout = layout;
+    }
+
+    // returns the position of every cell of the sheet
+    fn sheet_layout(&self) -> SheetLayout {
+        let component_ingredients: Vec<Vec<usize>> = self
+            .component_order
+            .iter()
+            .map(|comp_name| {
+                let comp = &self.components[comp_name];
+                (0..self.ingredient_order.len())
+                    .filter(|i| comp.ingredients.contains_key(&self.ingredient_order[*i]))
+                    .collect()
+            })
+            .collect();
+        SheetLayout::new(
+            self.layout,
+            self.dough_serving.is_some(),
+            self.ingredient_order.len(),
+            component_ingredients,
+            self.seeds().len(),
+        )
+    }
+
+    // Returns the cells of the spreadsheet for the formula, ordered by row
+    // and then by column
+    pub fn to_sheet(&self) -> Result<Vec<CSVCell>, FormulaError> {
+        let layout = self.sheet_layout();
+        let mut cells: Vec<CSVCell> = Vec::new();
+        cells.append(&mut header_cells(self, &layout));
+        cells.append(&mut ingredient_label_cells(self, &layout));
+        cells.append(&mut component_percentages(self, &layout));
+        cells.append(&mut component_totals(self, &layout)?);
+        let component_shares: HashMap<String, CellExpr> = component_mass(self, &layout)?;
+        cells.append(&mut component_share_cells(self, &component_shares, &layout));
+        cells.append(&mut component_flour_cells(self, &layout));
+        cells.append(&mut overall_cells(self, &layout)?);
+        cells.append(&mut flour_marker_cells(self, &layout));
+        cells.append(&mut water_fraction_cells(self, &layout));
+        cells.append(&mut category_cells(self, &layout));
+        cells.append(&mut hydration_cells(self, &layout)?);
+        cells.append(&mut preferment_cells(self, &layout)?);
+        cells.append(&mut category_subtotal_cells(self, &layout)?);
+        if let Some(serving) = self.dough_serving {
+            cells.append(&mut dough_mass_cells(serving, self.batch_size, self.loss, self.mass_unit, &layout));
+            cells.append(&mut component_mass_cells(self, &layout)?);
+        }
+        cells.sort_by_key(|cell| (cell.position.row, cell.position.col));
+        Ok(cells)
+    }
+
+    // Returns a String of the csv representation of the formula
+    pub fn to_csv(&self) -> Result<String, FormulaError> {
+        self.to_csv_with_dialect(&CsvDialect::default())
+    }
+
+    // Returns a String of the csv representation of the formula in dialect
+    pub fn to_csv_with_dialect(&self, dialect: &CsvDialect) -> Result<String, FormulaError> {
+        csv_cells_to_grid_with_dialect(&self.to_sheet()?, dialect)
+    }
+
+    // Writes the csv representation of the formula to writer
+    pub fn write_csv<W: io::Write>(&self, writer: W) -> Result<(), FormulaError> {
+        self.write_csv_with_dialect(writer, &CsvDialect::default())
+    }
+
+    // Writes the csv representation of the formula in dialect to writer
+    pub fn write_csv_with_dialect<W: io::Write>(
+        &self,
+        mut writer: W,
+        dialect: &CsvDialect,
+    ) -> Result<(), FormulaError> {
+        let csv = self.to_csv_with_dialect(dialect)?;
+        writer
+            .write_all(csv.as_bytes())
+            .map_err(|e| FormulaError::Write { source: e })
+    }
+
+    // Writes the formula as an xlsx workbook to writer
+    pub fn write_xlsx<W: io::Write>(&self, mut writer: W) -> Result<(), FormulaError> {
+        let xlsx = cells_to_xlsx(&self.to_sheet()?)?;
+        writer
+            .write_all(&xlsx)
+            .map_err(|e| FormulaError::Write { source: e })
+    }
+
+    // Writes the formula as an OpenDocument spreadsheet to writer
+    pub fn write_ods<W: io::Write>(&self, mut writer: W) -> Result<(), FormulaError> {
+        let ods = cells_to_ods(&self.to_sheet()?)?;
+        writer
+            .write_all(&ods)
+            .map_err(|e| FormulaError::Write { source: e })
+    }
+
+    // Returns the formula in the schema it is read from, with components and
+    // their ingredients in source order. Masses are baker's percentages, so
+    // the flour of every component adds up to 100
+    pub fn to_spec(&self) -> FormulaSpec {
+        let components = self
+            .component_order
+            .iter()
+            .map(|comp_name| {
+                let comp = &self.components[comp_name];
+                let ingredients = comp
+                    .ingredient_order
+                    .iter()
+                    .map(|ing_name| {
+                        let (percentage, is_flour) = match &comp.ingredients[ing_name] {
+                            Ingredient::Flour(x) => (x, true),
+                            Ingredient::NonFlour(x) => (x, false),
+                        };
+                        // categories that can be inferred are left out
+                        let category = self
+                            .category(ing_name)
+                            .filter(|category| *category != Category::infer(ing_name, is_flour));
+                        let water = self.water_fraction(ing_name).filter(|water| {
+                            *water != category::water_fraction(ing_name, self.category(ing_name).unwrap_or(Category::Other))
+                        });
+                        IngredientSpec {
+                            category,
+                            water,
+                            notes: comp.notes.get(ing_name).cloned(),
+                            ..IngredientSpec::new(ing_name.clone(), percentage * dec!(100), is_flour)
+                        }
+                    })
+                    .collect();
+                ComponentSpec {
+                    name: comp_name.clone(),
+                    ingredients,
+                    location: comp.location,
+                }
+            })
+            .collect();
+        FormulaSpec {
+            version: SCHEMA_VERSION,
+            name: self.name.clone(),
+            dough_serving: self.dough_serving,
+            batch_size: Some(self.batch_size),
+            loss: Some(self.loss).filter(|loss| !loss.is_zero()),
+            components,
+        }
+    }
+
+    // Returns the formula in the yaml format read by yaml_to_dough_formula,
+    // with masses rounded to the default precision
+    pub fn to_yaml(&self) -> String {
+        self.to_spec().round_masses(Precision::default()).to_yaml()
+    }
+
+    // Returns the formula in the json format read by json_to_dough_formula,
+    // with masses rounded to the default precision
+    pub fn to_json(&self) -> String {
+        self.to_spec().round_masses(Precision::default()).to_json()
+    }
 }
 
-pub fn yaml_to_dough_formula(filename: String) -> DoughFormula {
-    let filename: String = fs::read_to_string(filename).expect("Unable to read file");
-    let docs = YamlLoader::load_from_str(&filename).unwrap();
-    let doc = &docs[0];
-
-    // calculate flour totals for each segment
-    let mut component_flour: HashMap<String, Decimal> = HashMap::new();
-    for s in doc["components"].as_vec().unwrap() {
-        let name = s["name"].as_str().unwrap().to_string();
-        for ing in s["ingredients"].as_vec().unwrap() {
-            let is_flour = ing[2].as_bool().unwrap();
-            if is_flour {
-                let mass: Decimal = Decimal::from_f64(ing[1].as_f64().unwrap()).unwrap();
-                if !component_flour.contains_key(&name) {
-                    component_flour.insert(name.clone(), dec!(0));
+impl fmt::Display for DoughFormula {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.name)?;
+        for comp_name in &self.component_order {
+            writeln!(f, "  {}", comp_name)?;
+            let comp = &self.components[comp_name];
+            for ing_name in &self.ingredient_order {
+                if let Some(ing) = comp.ingredients.get(ing_name) {
+                    let (percentage, kind) = match ing {
+                        Ingredient::Flour(x) => (x, "  flour"),
+                        Ingredient::NonFlour(x) => (x, ""),
+                    };
+                    let percentage = Precision::default().round(percentage * dec!(100));
+                    writeln!(f, "    {:<24}{:>10}%{}", ing_name, percentage, kind)?;
                 }
-                let new_component_flour = component_flour[&name] + mass;
-                component_flour.insert(name.clone(), new_component_flour);
             }
         }
+        Ok(())
     }
+}
+
+pub fn yaml_to_dough_formula(filename: String) -> Result<DoughFormula, FormulaError> {
+    let source: String = fs::read_to_string(&filename).map_err(|e| FormulaError::Io {
+        path: filename.clone(),
+        source: e,
+    })?;
+    yaml_str_to_dough_formula(&source)
+}
+
+// Same as yaml_to_dough_formula, for a formula that is already in memory
+pub fn yaml_str_to_dough_formula(source: &str) -> Result<DoughFormula, FormulaError> {
+    build_formula(FormulaSpec::from_yaml_str(source)?)
+}
 
-    // initialize struct
-    let formula_name = doc["name"].as_str().unwrap().to_string();
+// Reads a formula with the same structure as the yaml format from json
+pub fn json_to_dough_formula(filename: String) -> Result<DoughFormula, FormulaError> {
+    let source: String = fs::read_to_string(&filename).map_err(|e| FormulaError::Io {
+        path: filename.clone(),
+        source: e,
+    })?;
+    json_str_to_dough_formula(&source)
+}
+
+pub fn json_str_to_dough_formula(source: &str) -> Result<DoughFormula, FormulaError> {
+    spec_to_dough_formula(FormulaSpec::from_json_str(source)?)
+}
+
+// Reads a formula with the same structure as the yaml format from toml,
+// where components are an array of tables:
+//   name = "bread"
+//   [[components]]
+//   name = "mix"
+//   ingredients = [["bread flour", 1000, true], {name = "water", mass = 700}]
+pub fn toml_to_dough_formula(filename: String) -> Result<DoughFormula, FormulaError> {
+    let source: String = fs::read_to_string(&filename).map_err(|e| FormulaError::Io {
+        path: filename.clone(),
+        source: e,
+    })?;
+    toml_str_to_dough_formula(&source)
+}
+
+pub fn toml_str_to_dough_formula(source: &str) -> Result<DoughFormula, FormulaError> {
+    spec_to_dough_formula(FormulaSpec::from_toml_str(source)?)
+}
+
+// Builds a DoughFormula out of a formula deserialized from any format,
+// checking its values first. Errors have no location
+pub fn spec_to_dough_formula(spec: FormulaSpec) -> Result<DoughFormula, FormulaError> {
+    spec.validate()?;
+    build_formula(spec)
+}
+
+// Builds a DoughFormula out of a formula whose values have been checked,
+// either as a yaml or csv file was read or by FormulaSpec::validate for
+// json, toml and other deserialized formulas. Percentages are relative to
+// the flour of each component and the component graph is checked for a mix,
+// cycles and unreferenced components
+fn build_formula(spec: FormulaSpec) -> Result<DoughFormula, FormulaError> {
     let mut formula = DoughFormula {
-        name: formula_name,
+        name: spec.name,
         components: HashMap::new(),
         flour: HashSet::new(),
         non_flour: HashSet::new(),
+        categories: HashMap::new(),
+        water_fractions: HashMap::new(),
+        component_order: Vec::new(),
+        ingredient_order: Vec::new(),
+        dough_serving: spec.dough_serving,
+        batch_size: spec.batch_size.unwrap_or(dec!(1)),
+        loss: spec.loss.unwrap_or(dec!(0)),
+        layout: LayoutKind::default(),
+        mass_unit: Unit::default(),
     };
 
-    // convert yaml to struct DoughFormula
-    for (_, s) in doc["components"].as_vec().unwrap().iter().enumerate() {
-        let seg_name = s["name"].as_str().unwrap().to_string();
+    // ingredients given a category, which takes the place of an inferred one
+    let mut given_category: HashSet<String> = HashSet::new();
+    let mut given_water: HashMap<String, Decimal> = HashMap::new();
+    for raw in spec.components {
+        let seg_name = raw.name;
+        if formula.components.contains_key(&seg_name) {
+            return Err(FormulaError::DuplicateComponent {
+                component: seg_name,
+                location: raw.location,
+            });
+        }
+
+        // masses in grams, whatever unit they are written in
+        let mut grams: Vec<Decimal> = Vec::new();
+        for ing in &raw.ingredients {
+            grams.push(ing.grams().ok_or_else(|| {
+                let (field, expected) = ing.grams_expected();
+                invalid_field(field, Some(&seg_name), Some(&ing.name), expected, ing.location)
+            })?);
+        }
+
+        // calculate flour total for the component
+        let component_flour: Decimal = raw
+            .ingredients
+            .iter()
+            .zip(&grams)
+            .filter(|(ing, _)| ing.flour)
+            .map(|(_, mass)| mass)
+            .sum();
+        if component_flour.is_zero() {
+            return Err(FormulaError::ZeroFlour {
+                component: seg_name,
+                location: raw.location,
+            });
+        }
+
         let mut seg: DoughComponent = DoughComponent {
+            name: seg_name.clone(),
             ingredients: HashMap::new(),
+            ingredient_order: Vec::new(),
+            notes: HashMap::new(),
+            location: raw.location,
         };
-
-        for ing in s["ingredients"].as_vec().unwrap() {
-            let ing_name = ing[0].as_str().unwrap().to_string();
-            let mass = ing[1].as_f64().unwrap();
-            let is_flour = ing[2].as_bool().unwrap();
-            let percentage = Decimal::from_f64(mass).unwrap() / component_flour[&seg_name];
-            let new_ing: Ingredient;
-            if is_flour {
-                formula.flour.insert(ing_name.clone());
-                new_ing = Ingredient::Flour(percentage);
+        for (ing, mass) in raw.ingredients.into_iter().zip(grams) {
+            if seg.ingredients.contains_key(&ing.name) {
+                return Err(FormulaError::DuplicateIngredient {
+                    component: seg_name,
+                    ingredient: ing.name,
+                    location: ing.location,
+                });
+            }
+            let conflicting = if ing.flour {
+                formula.non_flour.contains(&ing.name)
+            } else {
+                formula.flour.contains(&ing.name)
+            };
+            if conflicting {
+                return Err(FormulaError::FlourConflict {
+                    component: seg_name,
+                    ingredient: ing.name,
+                    location: ing.location,
+                });
+            }
+            let category = ing.category();
+            match formula.categories.get(&ing.name) {
+                Some(known) if *known != category && ing.category.is_some() => {
+                    if given_category.contains(&ing.name) {
+                        return Err(FormulaError::CategoryConflict {
+                            component: seg_name,
+                            ingredient: ing.name,
+                            location: ing.location,
+                        });
+                    }
+                    formula.categories.insert(ing.name.clone(), category);
+                }
+                Some(_) => (),
+                None => {
+                    formula.categories.insert(ing.name.clone(), category);
+                }
+            }
+            if ing.category.is_some() {
+                given_category.insert(ing.name.clone());
+            }
+            if let Some(water) = ing.water {
+                if given_water.get(&ing.name).is_some_and(|given| *given != water) {
+                    return Err(FormulaError::WaterConflict {
+                        component: seg_name,
+                        ingredient: ing.name,
+                        location: ing.location,
+                    });
+                }
+                given_water.insert(ing.name.clone(), water);
+            }
+            let percentage = mass / component_flour;
+            let new_ing: Ingredient = if ing.flour {
+                formula.flour.insert(ing.name.clone());
+                Ingredient::Flour(percentage)
             } else {
-                formula.non_flour.insert(ing_name.clone());
-                new_ing = Ingredient::NonFlour(percentage);
+                formula.non_flour.insert(ing.name.clone());
+                Ingredient::NonFlour(percentage)
+            };
+            seg.ingredient_order.push(ing.name.clone());
+            if let Some(notes) = ing.notes {
+                seg.notes.insert(ing.name.clone(), notes);
             }
-            seg.ingredients.insert(ing_name, new_ing);
+            seg.ingredients.insert(ing.name, new_ing);
         }
+        formula.component_order.push(seg_name.clone());
         formula.components.insert(seg_name, seg);
     }
 
-    // obtain ordering of components and ingredients
-    let mut flour: Vec<String> = formula.flour.clone().into_iter().collect();
-    let mut non_flour: Vec<String> = formula.non_flour.clone().into_iter().collect();
-    flour.sort();
-    flour.reverse();
-    non_flour.sort();
-    non_flour.reverse();
-    flour.append(&mut non_flour);
-    let ingredient_order = flour;
-    let mut dup_check = ingredient_order.clone();
-    dup_check.sort();
-    if dup_check.len() != ingredient_order.len() {
-        panic!("Duplicate component names are not allowed");
+    if !formula.components.contains_key(MIX) {
+        return Err(FormulaError::MissingMix);
     }
-
-    let components: Vec<String> = formula.components.keys().cloned().collect();
-    if components
-        .iter()
-        .filter(|&c| (*c == String::from(MIX)))
-        .count()
-        != 1
-    {
-        panic!("Must be exactly one component named 'mix'");
+    let components = &formula.components;
+    formula.categories.retain(|ing_name, _| !components.contains_key(ing_name));
+    // once every category is known, as the default depends on it
+    for (ing_name, category) in &formula.categories {
+        let water = given_water
+            .get(ing_name)
+            .copied()
+            .unwrap_or_else(|| category::water_fraction(ing_name, *category));
+        formula.water_fractions.insert(ing_name.clone(), water);
     }
 
     // dfs to check for cycle in component graph rooted at 'mix'
-    let mut component_order = bfs_components(&formula.components);
-    component_order.reverse();
     validate_structure(
         MIX,
         &formula.components,
         &mut HashSet::new(),
         &mut HashSet::new(),
-    );
+    )?;
+    formula.sort_ingredients(IngredientOrder::Source);
+    Ok(formula)
+}
 
-    let component_totals: HashMap<String, CSVCell> = component_totals(&component_order, ingredient_order.len());
-    let component_percentages: HashMap<String, HashMap<String, CSVCell>> = component_percentages(&formula.components,
-                                                                                                    &component_order, 
-                                                                                                    &ingredient_order);
-    
-    let mut component_percentages_vec: Vec<CSVCell> = component_percentages.iter()
-                                                                       .flat_map(|(_,v)| v.values().cloned())
-                                                                       .collect();
-    let mut ingredient_labels = ingredient_label_cells(&ingredient_order, component_order.len());
-
-
-    let mut comp_totals_vec: Vec<CSVCell> = component_totals.values().cloned().collect();
-    
-    // Testing CSVCell
-    let mut test_cells = Vec::new();
-    test_cells.append(&mut  comp_totals_vec);
-    test_cells.append(&mut component_percentages_vec);
-    test_cells.append(&mut ingredient_labels);
-    let test_grid_just_totals = csv_cells_to_grid(&test_cells);
-    println!("{}",  test_grid_just_totals);
-    
-    formula
+
+pub fn csv_to_dough_formula(filename: String) -> Result<DoughFormula, FormulaError> {
+    csv_to_dough_formula_with_dialect(filename, &CsvDialect::default())
 }
 
+pub fn csv_to_dough_formula_with_dialect(
+    filename: String,
+    dialect: &CsvDialect,
+) -> Result<DoughFormula, FormulaError> {
+    let source: String = fs::read_to_string(&filename).map_err(|e| FormulaError::Io {
+        path: filename.clone(),
+        source: e,
+    })?;
+    csv_str_to_dough_formula_with_dialect(&source, dialect)
+}
 
-// returns a Vec<CSVCell> that represents the cells for ingredient labels
-fn ingredient_label_cells(
-    ing_ordering: &Vec<String>,
-    num_components: usize
-) -> Vec<CSVCell> {
-    let mut result: Vec<CSVCell> = Vec::new();
-    for (index, ing_name) in ing_ordering.iter().enumerate() {
-        let label_ref_1 = CellRef {
-            pos: CellPosition {row: (ROW_OFFSET + index) as u32, col: 0},
-            fix_row: false,
-            fix_col: false,
-        };
+pub fn csv_str_to_dough_formula(source: &str) -> Result<DoughFormula, FormulaError> {
+    csv_str_to_dough_formula_with_dialect(source, &CsvDialect::default())
+}
 
-        let label_position_2 = CellPosition {
-            row: label_ref_1.pos.row,
-            col: 1 + 2*num_components as u32
-        };
+// Reads a sheet in any layout written by to_csv, which may have been edited
+// in a spreadsheet application and saved with either formulas or values.
+//  - the layout is told by where the "%" title of the first component is
+//  - the formula name is the top left cell and the component names and
+//    ingredients are found as described in side_by_side_names and
+//    stacked_names
+//  - an ingredient is in a component if its percentage cell is not empty and
+//    is flour if its row carries a flour marker
+//  - the water fraction and category of an ingredient follow its flour
+//    marker, empty ones are inferred from its name
+//  - dough serving, batch size and loss are read from their labelled rows,
+//    the dough serving is in grams whatever the unit of the mass columns
+// Locations in errors are the row and column of the offending cell
+pub fn csv_str_to_dough_formula_with_dialect(
+    source: &str,
+    dialect: &CsvDialect,
+) -> Result<DoughFormula, FormulaError> {
+    let cells = csv_grid_to_cells_with_dialect(source, dialect)?;
+    let mut evaluator = Evaluator::new(&cells);
+    let mut text_at = |row: u32, col: u32| -> Result<Option<String>, FormulaError> {
+        match evaluator.value(&CellPosition { row, col })? {
+            Value::Text(s) if !s.trim().is_empty() => Ok(Some(s.trim().to_string())),
+            _ => Ok(None),
+        }
+    };
 
-        let label_cell_1 = CSVCell {
-            value: CellValue::Str(ing_name.to_string()),
-            position: label_ref_1.pos.clone(),
+    let name = text_at(0, 0)?.ok_or_else(|| FormulaError::MissingField {
+        field: String::from("name"),
+        component: None,
+        location: Location { line: 1, col: 1 },
+    })?;
+    let mut kind: Option<LayoutKind> = None;
+    for candidate in [LayoutKind::Standard, LayoutKind::Transposed, LayoutKind::Stacked] {
+        let title = SheetLayout::new(candidate, true, 0, vec![Vec::new()], 0).component_titles(0).0;
+        if text_at(title.row, title.col)?.is_some_and(|text| text == PERCENT_TITLE) {
+            kind = Some(candidate);
+            break;
+        }
+    }
+    let kind = kind.ok_or(FormulaError::UnknownLayout)?;
+    let (comp_names, ing_names, layout) = match kind {
+        LayoutKind::Stacked => stacked_names(&mut text_at)?,
+        _ => side_by_side_names(kind, &mut text_at)?,
+    };
+    let mass_title = layout.overall_titles().1;
+    let mass_unit = text_at(mass_title.row, mass_title.col)?
+        .and_then(|symbol| symbol.parse::<Unit>().ok())
+        .filter(Unit::is_mass)
+        .unwrap_or_default();
+    let mut ing_flour: Vec<bool> = Vec::new();
+    for i in 0..ing_names.len() {
+        let marker = layout.flour_marker(i);
+        ing_flour.push(text_at(marker.row, marker.col)?.is_some_and(|m| m.eq_ignore_ascii_case(FLOUR_MARKER)));
+    }
+    // categories and water fractions are only kept where they differ from
+    // the default
+    let mut ing_category: Vec<Option<Category>> = Vec::new();
+    for (i, ing_name) in ing_names.iter().enumerate() {
+        let pos = layout.category(i);
+        let category = match text_at(pos.row, pos.col)? {
+            None => None,
+            Some(text) => Some(text.parse::<Category>().map_err(|_| {
+                invalid_field("category", None, Some(ing_name), CATEGORY_EXPECTED, location(&pos))
+            })?),
+        };
+        if category.is_some_and(|category| (category == Category::Flour) != ing_flour[i]) {
+            return Err(invalid_field("category", None, Some(ing_name), FLOUR_CATEGORY_EXPECTED, location(&pos)));
+        }
+        ing_category.push(category.filter(|category| *category != Category::infer(ing_name, ing_flour[i])));
+    }
+    let mut ing_water: Vec<Option<Decimal>> = Vec::new();
+    for (i, ing_name) in ing_names.iter().enumerate() {
+        let pos = layout.water_fraction(i);
+        let water = match evaluator.value(&pos)? {
+            Value::Empty => None,
+            Value::Number(x) if !x.is_sign_negative() && x <= dec!(1) => Some(x),
+            _ => {
+                return Err(invalid_field(
+                    "water",
+                    None,
+                    Some(ing_name),
+                    "a number from 0 to 1",
+                    location(&pos),
+                ))
+            }
         };
+        let category = ing_category[i].unwrap_or_else(|| Category::infer(ing_name, ing_flour[i]));
+        let default = category::water_fraction(ing_name, category);
+        ing_water.push(water.filter(|water| *water != default));
+    }
+
+    let mut components: Vec<ComponentSpec> = Vec::new();
+    for (c, comp_name) in comp_names.into_iter().enumerate() {
+        let mut ingredients: Vec<IngredientSpec> = Vec::new();
+        for (i, ing_name) in ing_names.iter().enumerate() {
+            // the stacked layout only has cells for the ingredients of the
+            // component
+            let Some(pos) = layout.percent(c, i) else { continue };
+            let percentage = match evaluator.value(&pos)? {
+                Value::Empty => continue,
+                Value::Number(x) if !x.is_sign_negative() => x,
+                _ => {
+                    return Err(invalid_field(
+                        "percentage",
+                        Some(&comp_name),
+                        Some(ing_name),
+                        "a non-negative number",
+                        location(&pos),
+                    ))
+                }
+            };
+            ingredients.push(IngredientSpec {
+                category: ing_category[i],
+                water: ing_water[i],
+                location: location(&pos),
+                ..IngredientSpec::new(ing_name.to_string(), percentage, ing_flour[i])
+            });
+        }
+        components.push(ComponentSpec {
+            name: comp_name,
+            ingredients,
+            location: location(&layout.component_header(c)),
+        });
+    }
 
-        let label_cell_2 = CSVCell {
-            value: CellValue::Expr(CellExpr::Ref(label_ref_1)),
-            position: label_position_2,
+    // inputs below the ingredients, found by their labels
+    let first_row = layout.total_percent(0).row;
+    let mut labelled_input = |input: Input, expected: &str, is_valid: fn(Decimal) -> bool| {
+        let label = input.label();
+        let (label_pos, value_pos) = layout.input(input);
+        let label_cell = cells.iter().find(|c| {
+            c.position.col == label_pos.col
+                && c.position.row >= first_row
+                && matches!(&c.value, CellValue::Str(s) if s.trim() == label)
+        });
+        match label_cell {
+            None => Ok(None),
+            Some(cell) => {
+                let pos = CellPosition { row: cell.position.row, col: value_pos.col };
+                match evaluator.value(&pos)? {
+                    Value::Number(x) if is_valid(x) => Ok(Some(x)),
+                    _ => Err(invalid_field(label, None, None, expected, location(&pos))),
+                }
+            }
+        }
+    };
+    let dough_serving = labelled_input(Input::DoughServing, "a positive number", |x| {
+        x.is_sign_positive() && !x.is_zero()
+    })?;
+    let batch_size = labelled_input(Input::BatchSize, "a positive number", |x| {
+        x.is_sign_positive() && !x.is_zero()
+    })?;
+    let loss = labelled_input(Input::Loss, "a number from 0 up to but excluding 1", |x| {
+        !x.is_sign_negative() && x < dec!(1)
+    })?;
+    let mut formula = build_formula(FormulaSpec {
+        version: SCHEMA_VERSION,
+        name,
+        dough_serving,
+        batch_size,
+        loss,
+        components,
+    })?;
+    formula.mass_unit = mass_unit;
+    formula.layout = kind;
+    Ok(formula)
+}
+
+// the location of pos in a csv file
+fn location(pos: &CellPosition) -> Location {
+    Location {
+        line: pos.row as usize + 1,
+        col: pos.col as usize + 1,
+    }
+}
+
+// Returns the component names and ingredients of a standard or transposed
+// sheet, with its layout. The component names are the header above the "%"
+// titles of the percentage columns, the ingredients are the labels that are
+// repeated in the overall formula, which ends them before the totals
+fn side_by_side_names(
+    kind: LayoutKind,
+    text_at: &mut impl FnMut(u32, u32) -> Result<Option<String>, FormulaError>,
+) -> Result<(Vec<String>, Vec<String>, SheetLayout), FormulaError> {
+    // the component headers and ingredient labels do not depend on the size
+    // of the sheet
+    let probe = SheetLayout::new(kind, true, 0, Vec::new(), 0);
+    let mut comp_names: Vec<String> = Vec::new();
+    loop {
+        let header = probe.component_header(comp_names.len());
+        match text_at(header.row, header.col)? {
+            Some(comp_name) => comp_names.push(comp_name),
+            None => break,
+        }
+    }
+    if comp_names.is_empty() {
+        return Err(FormulaError::MissingField {
+            field: String::from("components"),
+            component: None,
+            location: location(&probe.component_header(0)),
+        });
+    }
+    let columns = SheetLayout::new(kind, true, 0, vec![Vec::new(); comp_names.len()], 0);
+    let mut ing_names: Vec<String> = Vec::new();
+    while let Some(label) = probe.ingredient_label(ing_names.len()) {
+        let overall_label = columns.overall_label(ing_names.len());
+        match (text_at(label.row, label.col)?, text_at(overall_label.row, overall_label.col)?) {
+            (Some(ing_name), Some(repeated)) if ing_name == repeated => ing_names.push(ing_name),
+            _ => break,
+        }
+    }
+    let layout = SheetLayout::new(kind, true, ing_names.len(), vec![Vec::new(); comp_names.len()], 0);
+    Ok((comp_names, ing_names, layout))
+}
+
+// Returns the component names and ingredients of a stacked sheet, with its
+// layout. The tables are read from the top, each a header, the labels of its
+// ingredients and a totals row. The table of a component is followed by its
+// share, the last table is the overall formula and lists every ingredient
+fn stacked_names(
+    text_at: &mut impl FnMut(u32, u32) -> Result<Option<String>, FormulaError>,
+) -> Result<(Vec<String>, Vec<String>, SheetLayout), FormulaError> {
+    let mut comp_names: Vec<String> = Vec::new();
+    let mut comp_labels: Vec<Vec<(String, CellPosition)>> = Vec::new();
+    let mut row = 1;
+    let ing_names: Vec<String> = loop {
+        let header = CellPosition { row, col: 0 };
+        let Some(name) = text_at(header.row, header.col)? else {
+            return Err(FormulaError::MissingField {
+                field: String::from("components"),
+                component: None,
+                location: location(&header),
+            });
         };
+        let mut labels: Vec<(String, CellPosition)> = Vec::new();
+        let mut pos = CellPosition { row: row + 1, col: 0 };
+        loop {
+            match text_at(pos.row, pos.col)? {
+                Some(label) if label == TOTAL_LABEL => break,
+                Some(label) => labels.push((label, pos.clone())),
+                None => {
+                    return Err(FormulaError::MissingField {
+                        field: String::from(TOTAL_LABEL),
+                        component: Some(name),
+                        location: location(&pos),
+                    })
+                }
+            }
+            pos.row += 1;
+        }
+        if text_at(pos.row + 1, 0)?.as_deref() != Some(SHARE_LABEL) {
+            break labels.into_iter().map(|(label, _)| label).collect();
+        }
+        comp_names.push(name);
+        comp_labels.push(labels);
+        // totals, share, flour and a blank row
+        row = pos.row + 4;
+    };
+    if comp_names.is_empty() {
+        return Err(FormulaError::MissingField {
+            field: String::from("components"),
+            component: None,
+            location: location(&CellPosition { row: 1, col: 0 }),
+        });
+    }
+    let mut component_ingredients: Vec<Vec<usize>> = Vec::new();
+    for (comp_name, labels) in comp_names.iter().zip(comp_labels) {
+        let mut ingredients: Vec<usize> = Vec::new();
+        for (label, pos) in labels {
+            match ing_names.iter().position(|ing_name| *ing_name == label) {
+                Some(i) => ingredients.push(i),
+                None => {
+                    return Err(invalid_field(
+                        "ingredient",
+                        Some(comp_name),
+                        Some(&label),
+                        "an ingredient of the overall formula",
+                        location(&pos),
+                    ))
+                }
+            }
+        }
+        component_ingredients.push(ingredients);
+    }
+    let layout = SheetLayout::new(LayoutKind::Stacked, true, ing_names.len(), component_ingredients, 0);
+    Ok((comp_names, ing_names, layout))
+}
 
-        result.push(label_cell_1);
-        result.push(label_cell_2);
+// returns a reference to pos, fixed in both directions if fixed is true
+fn cell_ref(pos: CellPosition, fixed: bool) -> CellRef {
+    CellRef {
+        pos,
+        fix_row: fixed,
+        fix_col: fixed,
+    }
+}
+
+fn text_cell(text: &str, position: CellPosition) -> CSVCell {
+    CSVCell {
+        value: CellValue::Str(text.to_string()),
+        position,
+    }
+}
+
+fn expr_cell(expr: CellExpr, position: CellPosition) -> CSVCell {
+    CSVCell {
+        value: CellValue::Expr(expr),
+        position,
+    }
+}
+
+fn binary(op: BinOp, left: CellExpr, right: CellExpr) -> CellExpr {
+    CellExpr::BinaryOp(op, Box::new(left), Box::new(right))
+}
+
+// returns the sum of exprs, or None if there are none
+fn sum_of(exprs: impl IntoIterator<Item = CellExpr>) -> Option<CellExpr> {
+    exprs
+        .into_iter()
+        .reduce(|total, expr| binary(BinOp::Add, total, expr))
+}
+
+// returns a FormulaError::InvalidField for a value given to a setter that
+// breaks the rule of its field
+fn check_setting(field: &str, x: Decimal, expected: &str, is_valid: fn(Decimal) -> bool) -> Result<(), FormulaError> {
+    if is_valid(x) {
+        Ok(())
+    } else {
+        Err(invalid_field(field, None, None, expected, Location::default()))
+    }
+}
+
+// returns a Vec<CSVCell> holding the formula name in the title cell, the
+// name of each component and of the overall formula in its header with the
+// "%" and mass unit titles of their columns below, and the labels of the totals
+fn header_cells(formula: &DoughFormula, layout: &SheetLayout) -> Vec<CSVCell> {
+    let mut result: Vec<CSVCell> = vec![text_cell(&formula.name, layout.title())];
+    let mut titles: Vec<(CellPosition, CellPosition)> = Vec::new();
+    for (c, comp_name) in formula.component_order.iter().enumerate() {
+        result.push(text_cell(comp_name, layout.component_header(c)));
+        titles.push(layout.component_titles(c));
+    }
+    result.push(text_cell(OVERALL, layout.overall_header()));
+    titles.push(layout.overall_titles());
+    for (percent_pos, mass_pos) in titles {
+        result.push(text_cell(PERCENT_TITLE, percent_pos));
+        if layout.has_mass() {
+            result.push(text_cell(formula.mass_unit.symbol(), mass_pos));
+        }
+    }
+    for pos in layout.total_labels() {
+        result.push(text_cell(TOTAL_LABEL, pos));
+    }
+    result
+}
+
+// returns a Vec<CSVCell> marking the flour ingredients after the overall
+// formula, so that an edited sheet can be read back
+fn flour_marker_cells(formula: &DoughFormula, layout: &SheetLayout) -> Vec<CSVCell> {
+    formula
+        .ingredient_order
+        .iter()
+        .enumerate()
+        .filter(|(_, ing_name)| formula.flour.contains(*ing_name))
+        .map(|(i, _)| text_cell(FLOUR_MARKER, layout.flour_marker(i)))
+        .collect()
+}
+
+// returns a Vec<CSVCell> that represents the cells for ingredient labels,
+// both next to the components and in the overall formula. The overall
+// labels refer to the ingredient label where there is a single one
+fn ingredient_label_cells(formula: &DoughFormula, layout: &SheetLayout) -> Vec<CSVCell> {
+    let mut result: Vec<CSVCell> = Vec::new();
+    for (i, pos) in layout.ingredient_labels() {
+        result.push(text_cell(&formula.ingredient_order[i], pos));
+    }
+    for (i, ing_name) in formula.ingredient_order.iter().enumerate() {
+        let label_pos = layout.overall_label(i);
+        match layout.ingredient_label(i) {
+            Some(pos) => result.push(expr_cell(CellExpr::Ref(cell_ref(pos, false)), label_pos)),
+            None => result.push(text_cell(ing_name, label_pos)),
+        }
     }
     result
 }
 
-// returns a HashMap<String, CSVCell> that maps component names to the
-// CSVCell associated with the position and expression for the component's
-// percentage total
+// returns a Vec<CSVCell> holding the percentage total of every component
 fn component_totals(
-    comp_ordering: &Vec<String>,
-    num_ingredients: usize,
-) -> HashMap<String, CSVCell> {
-    let mut result: HashMap<String, CSVCell> = HashMap::new();
-    for (index, comp_name) in comp_ordering.iter().enumerate() {
-        let total_ref = CellRef {
-            pos: CellPosition {
-                row: (ROW_OFFSET + num_ingredients) as u32,
-                col: (2*index + COL_OFFSET) as u32
-            },
-            fix_row: false,
-            fix_col: false,
-        };
-        let from = CellRef {
-            pos: CellPosition { row: total_ref.pos.row - num_ingredients as u32,
-                col: total_ref.pos.col },
-            fix_row: false,
-            fix_col: false,
+    formula: &DoughFormula,
+    layout: &SheetLayout,
+) -> Result<Vec<CSVCell>, FormulaError> {
+    (0..formula.component_order.len())
+        .map(|c| {
+            Ok(expr_cell(
+                CellExpr::Sum(layout.percent_range(c)?),
+                layout.total_percent(c),
+            ))
+        })
+        .collect()
+}
+
+// returns a Vec<CSVCell> holding the "component share" cells. Each cell is
+// the fraction of the total mass of mix that the component makes up
+fn component_share_cells(
+    formula: &DoughFormula,
+    component_shares: &HashMap<String, CellExpr>,
+    layout: &SheetLayout,
+) -> Vec<CSVCell> {
+    let mut result: Vec<CSVCell> = layout
+        .share_labels()
+        .into_iter()
+        .map(|pos| text_cell(SHARE_LABEL, pos))
+        .collect();
+    for (c, comp_name) in formula.component_order.iter().enumerate() {
+        result.push(expr_cell(component_shares[comp_name].clone(), layout.share(c)));
+    }
+    result
+}
+
+// returns a Vec<CSVCell> holding the "component flour" cells. Each cell is
+// the flour of the component as a fraction of the total mass of mix, which
+// is the component share divided by the percentage total of the component
+fn component_flour_cells(formula: &DoughFormula, layout: &SheetLayout) -> Vec<CSVCell> {
+    let mut result: Vec<CSVCell> = layout
+        .flour_labels()
+        .into_iter()
+        .map(|pos| text_cell("component flour", pos))
+        .collect();
+    for c in 0..formula.component_order.len() {
+        let flour_expr = binary(
+            BinOp::Div,
+            CellExpr::Ref(cell_ref(layout.share(c), true)),
+            CellExpr::Ref(cell_ref(layout.total_percent(c), true)),
+        );
+        result.push(expr_cell(flour_expr, layout.flour(c)));
+    }
+    result
+}
+
+// returns a Vec<CSVCell> holding the overall formula, the total of each
+// ingredient across all components relative to the total flour of the recipe.
+//  - the percentage of each ingredient is the sum of its percentage in each
+//    component times the component flour, divided by the total flour. The
+//    total flour is the sum of the component flour. Where the ingredient is
+//    in line with the component flour this is a SUMPRODUCT
+//  - the mass of each ingredient is filled if the dough mass is known
+//  - ingredients that are components are left out, their ingredients are
+//    already counted in the rows of those ingredients
+fn overall_cells(
+    formula: &DoughFormula,
+    layout: &SheetLayout,
+) -> Result<Vec<CSVCell>, FormulaError> {
+    let mut result: Vec<CSVCell> = Vec::new();
+    let num_components = formula.component_order.len();
+    let total_flour_ref = cell_ref(layout.total_flour(), true);
+    let total_flour = match layout.flour_line() {
+        Some((from, to)) => CellExpr::Sum(CellArray::new(cell_ref(from, true), cell_ref(to, true))?),
+        None => sum_of((0..num_components).map(|c| CellExpr::Ref(cell_ref(layout.flour(c), true))))
+            .expect("a formula has at least one component"),
+    };
+    result.push(expr_cell(total_flour, layout.total_flour()));
+    if let Some(pos) = layout.total_flour_label() {
+        result.push(text_cell("total flour", pos));
+    }
+    let (_, dough_mass_pos) = layout.input(Input::DoughMass);
+
+    for (i, ing_name) in formula.ingredient_order.iter().enumerate() {
+        if formula.components.contains_key(ing_name) {
+            continue;
+        }
+        let weighted = match (layout.ingredient_line(i), layout.flour_line()) {
+            (Some((from, to)), Some((flour_from, flour_to))) => {
+                // the row is relative so that the formula can be filled down
+                let line_ref = |pos: CellPosition| CellRef {
+                    pos,
+                    fix_row: layout.kind() == LayoutKind::Transposed,
+                    fix_col: layout.kind() != LayoutKind::Transposed,
+                };
+                CellExpr::sum_product(
+                    CellArray::new(line_ref(from), line_ref(to))?,
+                    CellArray::new(cell_ref(flour_from, true), cell_ref(flour_to, true))?,
+                )?
+            }
+            _ => sum_of((0..num_components).filter_map(|c| {
+                layout.percent(c, i).filter(|_| formula.components[&formula.component_order[c]]
+                    .ingredients
+                    .contains_key(ing_name))
+                    .map(|pos| {
+                        binary(
+                            BinOp::Mult,
+                            CellExpr::Ref(cell_ref(pos, false)),
+                            CellExpr::Ref(cell_ref(layout.flour(c), true)),
+                        )
+                    })
+            }))
+            .expect("every ingredient is in a component"),
         };
-        let to = CellRef {
-            pos: CellPosition { row: total_ref.pos.row - 1 as u32,
-            col: total_ref.pos.col },
-            fix_row: false,
-            fix_col: false,
+        let percent_expr = binary(BinOp::Div, weighted, CellExpr::Ref(total_flour_ref.clone()));
+        let percent_pos = layout.overall_percent(i);
+        if let Some(mass_pos) = layout.overall_mass(i) {
+            let flour_mass = binary(
+                BinOp::Mult,
+                CellExpr::Ref(total_flour_ref.clone()),
+                CellExpr::Ref(cell_ref(dough_mass_pos.clone(), true)),
+            );
+            let mass_expr = binary(BinOp::Mult, CellExpr::Ref(cell_ref(percent_pos.clone(), false)), flour_mass);
+            result.push(expr_cell(mass_expr, mass_pos));
+        }
+        result.push(expr_cell(percent_expr, percent_pos));
+    }
+
+    result.push(expr_cell(
+        CellExpr::Sum(layout.overall_percent_range()?),
+        layout.overall_total_percent(),
+    ));
+    if let Some(pos) = layout.overall_total_mass() {
+        result.push(expr_cell(CellExpr::Sum(layout.overall_mass_range()?), pos));
+    }
+    Ok(result)
+}
+
+// returns a Vec<CSVCell> holding the water fraction of every ingredient that
+// is not a component, after its flour marker, with a title above them
+fn water_fraction_cells(formula: &DoughFormula, layout: &SheetLayout) -> Vec<CSVCell> {
+    let mut result: Vec<CSVCell> = vec![text_cell(WATER_TITLE, layout.water_title())];
+    for (i, ing_name) in formula.ingredient_order.iter().enumerate() {
+        if let Some(water) = formula.water_fraction(ing_name) {
+            result.push(expr_cell(CellExpr::Percentage(water), layout.water_fraction(i)));
+        }
+    }
+    result
+}
+
+// returns a Vec<CSVCell> holding the category of every ingredient that is
+// not a component, after its water fraction, with a title above them
+fn category_cells(formula: &DoughFormula, layout: &SheetLayout) -> Vec<CSVCell> {
+    let mut result: Vec<CSVCell> = vec![text_cell(CATEGORY_TITLE, layout.category_title())];
+    for (i, ing_name) in formula.ingredient_order.iter().enumerate() {
+        if let Some(category) = formula.category(ing_name) {
+            result.push(text_cell(category.name(), layout.category(i)));
+        }
+    }
+    result
+}
+
+// returns a Vec<CSVCell> holding the hydration of every component and of
+// the overall formula: the sum of the percentages weighted by the water
+// fractions, divided by the sum of the flour percentages. Where the
+// percentages are in line with the water fractions this is a SUMPRODUCT.
+// Components count as dry in the components that hold them, they have no
+// water fraction
+fn hydration_cells(
+    formula: &DoughFormula,
+    layout: &SheetLayout,
+) -> Result<Vec<CSVCell>, FormulaError> {
+    let mut result: Vec<CSVCell> = Vec::new();
+    let flour_sum = |positions: Vec<CellPosition>| {
+        sum_of(positions.into_iter().map(|pos| CellExpr::Ref(cell_ref(pos, false))))
+            .expect("every component has flour")
+    };
+    let water_range = || -> Result<CellArray, FormulaError> {
+        let range = layout.water_range()?;
+        CellArray::new(
+            cell_ref(range.start().pos.clone(), true),
+            cell_ref(range.end().pos.clone(), true),
+        )
+    };
+    for (c, comp_name) in formula.component_order.iter().enumerate() {
+        let comp = &formula.components[comp_name];
+        let ingredients: Vec<usize> = (0..formula.ingredient_order.len())
+            .filter(|i| comp.ingredients.contains_key(&formula.ingredient_order[*i]))
+            .collect();
+        let water = match layout.kind() {
+            LayoutKind::Stacked => sum_of(ingredients.iter().filter_map(|i| {
+                formula.water_fraction(&formula.ingredient_order[*i])?;
+                let percent = layout.percent(c, *i)?;
+                Some(binary(
+                    BinOp::Mult,
+                    CellExpr::Ref(cell_ref(percent, false)),
+                    CellExpr::Ref(cell_ref(layout.water_fraction(*i), true)),
+                ))
+            }))
+            .unwrap_or(CellExpr::Number(dec!(0))),
+            _ => CellExpr::sum_product(layout.percent_range(c)?, water_range()?)?,
         };
-        let sum_array = CellArray::new(from, to);
-        let total_val = CellValue::Expr(CellExpr::Sum(sum_array));
-        let total_cell = CSVCell {
-            value: total_val,
-            position: total_ref.pos,
+        let flour = flour_sum(
+            ingredients
+                .iter()
+                .filter(|i| formula.flour.contains(&formula.ingredient_order[**i]))
+                .filter_map(|i| layout.percent(c, *i))
+                .collect(),
+        );
+        let (label_pos, value_pos) = layout.hydration(c);
+        result.push(text_cell(&format!("{} {}", comp_name, HYDRATION_LABEL), label_pos));
+        result.push(expr_cell(binary(BinOp::Div, water, flour), value_pos));
+    }
+    let water = CellExpr::sum_product(layout.overall_percent_range()?, water_range()?)?;
+    let flour = flour_sum(
+        formula
+            .ingredient_order
+            .iter()
+            .enumerate()
+            .filter(|(_, ing_name)| formula.flour.contains(*ing_name))
+            .map(|(i, _)| layout.overall_percent(i))
+            .collect(),
+    );
+    let (label_pos, value_pos) = layout.overall_hydration();
+    result.push(text_cell(&format!("{} {}", OVERALL, HYDRATION_LABEL), label_pos));
+    result.push(expr_cell(binary(BinOp::Div, water, flour), value_pos));
+    Ok(result)
+}
+
+// returns a Vec<CSVCell> holding the prefermented flour of every preferment,
+// its component flour divided by the total flour, and their total, followed
+// by the inoculation of every seed: its baker's percentage in the component
+// it is used in
+fn preferment_cells(
+    formula: &DoughFormula,
+    layout: &SheetLayout,
+) -> Result<Vec<CSVCell>, FormulaError> {
+    let mut result: Vec<CSVCell> = Vec::new();
+    let preferments = formula.component_order.iter().enumerate().filter(|(_, c)| *c != MIX);
+    let mut values: Vec<CellPosition> = Vec::new();
+    for (k, (c, comp_name)) in preferments.enumerate() {
+        let (label_pos, value_pos) = layout.prefermented_flour(k);
+        let flour = binary(
+            BinOp::Div,
+            CellExpr::Ref(cell_ref(layout.flour(c), true)),
+            CellExpr::Ref(cell_ref(layout.total_flour(), true)),
+        );
+        result.push(text_cell(&format!("{} {}", comp_name, PREFERMENTED_FLOUR_LABEL), label_pos));
+        result.push(expr_cell(flour, value_pos.clone()));
+        values.push(value_pos);
+    }
+    let total = match (values.first(), values.last()) {
+        (Some(first), Some(last)) if values.len() > 1 => CellExpr::Sum(CellArray::new(
+            cell_ref(first.clone(), false),
+            cell_ref(last.clone(), false),
+        )?),
+        (Some(first), _) => CellExpr::Ref(cell_ref(first.clone(), false)),
+        _ => CellExpr::Number(dec!(0)),
+    };
+    let (label_pos, value_pos) = layout.total_prefermented_flour();
+    result.push(text_cell(&format!("total {}", PREFERMENTED_FLOUR_LABEL), label_pos));
+    result.push(expr_cell(total, value_pos));
+    for (k, (seed, parent)) in formula.seeds().into_iter().enumerate() {
+        let percent = layout
+            .percent(formula.component_index(parent), formula.ingredient_index(seed))
+            .expect("a seed is an ingredient of its parent");
+        let (label_pos, value_pos) = layout.inoculation(k);
+        result.push(text_cell(&format!("{} {} of {}", seed, INOCULATION_LABEL, parent), label_pos));
+        result.push(expr_cell(CellExpr::Ref(cell_ref(percent, false)), value_pos));
+    }
+    Ok(result)
+}
+
+// returns a Vec<CSVCell> holding a subtotal of the overall percentage and
+// mass of every category with ingredients, labelled by the category. Rows of
+// a category that are next to each other, as they are when ingredients are
+// ordered by category, are summed as a range
+fn category_subtotal_cells(
+    formula: &DoughFormula,
+    layout: &SheetLayout,
+) -> Result<Vec<CSVCell>, FormulaError> {
+    let mut result: Vec<CSVCell> = Vec::new();
+    let subtotal = |positions: Vec<CellPosition>| -> Result<CellExpr, FormulaError> {
+        let is_range = positions.len() > 1
+            && positions.windows(2).all(|pair| {
+                (pair[1].row, pair[1].col) == (pair[0].row + 1, pair[0].col)
+                    || (pair[1].row, pair[1].col) == (pair[0].row, pair[0].col + 1)
+            });
+        if is_range {
+            let first = cell_ref(positions[0].clone(), false);
+            let last = cell_ref(positions[positions.len() - 1].clone(), false);
+            return Ok(CellExpr::Sum(CellArray::new(first, last)?));
+        }
+        Ok(sum_of(positions.into_iter().map(|pos| CellExpr::Ref(cell_ref(pos, false))))
+            .expect("only categories with ingredients have a subtotal"))
+    };
+    let present = Category::ALL
+        .into_iter()
+        .filter(|category| formula.categories.values().any(|c| c == category));
+    for (k, category) in present.enumerate() {
+        let indices: Vec<usize> = (0..formula.ingredient_order.len())
+            .filter(|i| formula.category(&formula.ingredient_order[*i]) == Some(category))
+            .collect();
+        let (label_pos, percent_pos, mass_pos) = layout.category_subtotal(k);
+        result.push(text_cell(&format!("{} {}", category, SUBTOTAL_LABEL), label_pos));
+        let percents = indices.iter().map(|i| layout.overall_percent(*i)).collect();
+        result.push(expr_cell(subtotal(percents)?, percent_pos));
+        if let Some(mass_pos) = mass_pos {
+            let masses = indices.iter().filter_map(|i| layout.overall_mass(*i)).collect();
+            result.push(expr_cell(subtotal(masses)?, mass_pos));
+        }
+    }
+    Ok(result)
+}
+
+// returns a Vec<CSVCell> holding the "dough serving", "batch size" and "loss"
+// input cells and the "dough mass" derived from them. The dough serving is
+// kept in grams, as given, so that it reads back exactly. Only the dough mass
+// is converted to mass_unit, the unit of the mass columns that follow from it
+fn dough_mass_cells(
+    dough_serving: Decimal,
+    batch_size: Decimal,
+    loss: Decimal,
+    mass_unit: Unit,
+    layout: &SheetLayout,
+) -> Vec<CSVCell> {
+    let mut result: Vec<CSVCell> = Vec::new();
+    let input_ref = |input: Input| CellExpr::Ref(cell_ref(layout.input(input).1, true));
+    let batch_mass = binary(BinOp::Mult, input_ref(Input::DoughServing), input_ref(Input::BatchSize));
+    let kept = binary(BinOp::Sub, CellExpr::Number(dec!(1)), input_ref(Input::Loss));
+    for input in Input::ALL {
+        let expr = match input {
+            Input::DoughServing => CellExpr::Number(dough_serving),
+            Input::BatchSize => CellExpr::Number(batch_size),
+            Input::Loss => CellExpr::Percentage(loss),
+            Input::DoughMass => {
+                let grams = binary(BinOp::Div, batch_mass.clone(), kept.clone());
+                match mass_unit.grams().expect("the mass unit is checked when set") {
+                    per_unit if per_unit == dec!(1) => grams,
+                    per_unit => binary(BinOp::Div, grams, CellExpr::Number(per_unit)),
+                }
+            }
         };
-        result.insert(comp_name.to_string(), total_cell);
+        let (label_pos, value_pos) = layout.input(input);
+        result.push(text_cell(input.label(), label_pos));
+        result.push(expr_cell(expr, value_pos));
     }
+    result.push(text_cell(Unit::Gram.symbol(), layout.input_unit(Input::DoughServing)));
+    result.push(text_cell(mass_unit.symbol(), layout.input_unit(Input::DoughMass)));
     result
 }
 
-// returns a HashMap that maps component names to another HashMap
-// The inner hashmap associated the component ingredients to their
-// percentage amount (as provided by input)
-fn component_percentages(
-    components: &HashMap<String, DoughComponent>,
-    component_order: &Vec<String>,
-    ingredient_order: &Vec<String>,
-) -> HashMap<String, HashMap<String, CSVCell>> {
-    let mut result: HashMap<String, HashMap<String, CSVCell>> = HashMap::new();
-    for (col, comp_name) in component_order.iter().enumerate() {
-        let comp: &DoughComponent = &components[comp_name];
-        let mut comp_percents: HashMap<String,CSVCell> = HashMap::new();
-        for (row, ing_name) in ingredient_order.iter().enumerate() {
-            if comp.ingredients.contains_key(ing_name) {
-                let ing_val = match comp.ingredients[ing_name] {
-                    Ingredient::Flour(x) => x,
-                    Ingredient::NonFlour(x) => x,
-                };
-    
-                let percent_ref = CellRef {
-                    pos: CellPosition {row: (ROW_OFFSET + row) as u32,
-                    col: (2*col + COL_OFFSET) as u32},
-                    fix_row: false,
-                    fix_col: false,
-                };
-    
-                let percent_cell = CSVCell {
-                    value: CellValue::Expr(CellExpr::Percentage(ing_val)),
-                    position: percent_ref.pos.clone(),
-                };
-    
-                comp_percents.insert(ing_name.to_string(),percent_cell);
+// returns a Vec<CSVCell> holding the mass cells of every component. The
+// flour mass of a component is its share of the dough mass divided by the
+// percentage total of the component
+fn component_mass_cells(
+    formula: &DoughFormula,
+    layout: &SheetLayout,
+) -> Result<Vec<CSVCell>, FormulaError> {
+    let mut result: Vec<CSVCell> = Vec::new();
+    let mass_ref = cell_ref(layout.input(Input::DoughMass).1, true);
+    for (c, comp_name) in formula.component_order.iter().enumerate() {
+        let comp = &formula.components[comp_name];
+        let comp_mass = binary(
+            BinOp::Mult,
+            CellExpr::Ref(cell_ref(layout.share(c), true)),
+            CellExpr::Ref(mass_ref.clone()),
+        );
+        let comp_flour = binary(
+            BinOp::Div,
+            comp_mass,
+            CellExpr::Ref(cell_ref(layout.total_percent(c), true)),
+        );
+        for (i, ing_name) in formula.ingredient_order.iter().enumerate() {
+            if !comp.ingredients.contains_key(ing_name) {
+                continue;
+            }
+            if let (Some(percent_pos), Some(mass_pos)) = (layout.percent(c, i), layout.mass(c, i)) {
+                let mass_expr = binary(
+                    BinOp::Mult,
+                    CellExpr::Ref(cell_ref(percent_pos, false)),
+                    comp_flour.clone(),
+                );
+                result.push(expr_cell(mass_expr, mass_pos));
+            }
+        }
+        if let Some(pos) = layout.total_mass(c) {
+            result.push(expr_cell(CellExpr::Sum(layout.mass_range(c)?), pos));
+        }
+    }
+    Ok(result)
+}
+
+// returns a Vec<CSVCell> holding the percentage of every ingredient in
+// every component, as provided by input
+fn component_percentages(formula: &DoughFormula, layout: &SheetLayout) -> Vec<CSVCell> {
+    let mut result: Vec<CSVCell> = Vec::new();
+    for (c, comp_name) in formula.component_order.iter().enumerate() {
+        let comp = &formula.components[comp_name];
+        for (i, ing_name) in formula.ingredient_order.iter().enumerate() {
+            let ing_val = match comp.ingredients.get(ing_name) {
+                Some(Ingredient::Flour(x)) | Some(Ingredient::NonFlour(x)) => *x,
+                None => continue,
+            };
+            if let Some(pos) = layout.percent(c, i) {
+                result.push(expr_cell(CellExpr::Percentage(ing_val), pos));
             }
         }
-        result.insert(comp_name.to_string(), comp_percents);
     }
     result
 }
 
+// returns a HashMap that maps component names to the expression for the
+// component's share of the total mass of mix
 fn component_mass(
-    components: &HashMap<String, DoughComponent>,
-    component_percentages: &HashMap<String, HashMap<String, CSVCell>>,
-) -> HashMap<String, CellExpr> {
+    formula: &DoughFormula,
+    layout: &SheetLayout,
+) -> Result<HashMap<String, CellExpr>, FormulaError> {
     let mut component_masses: HashMap<String, CellExpr> = HashMap::new();
+    component_masses.insert(MIX.to_string(), CellExpr::Percentage(dec!(1)));
     component_mass_aux(MIX,
-                        components, 
-                        component_percentages, 
-                        &mut component_masses, 
-                        &mut VecDeque::new(), 
-                        &mut HashSet::new(), 
-                        &mut HashSet::new());
-    component_masses
+                        formula,
+                        layout,
+                        &mut component_masses,
+                        &mut VecDeque::new(),
+                        &mut HashSet::new(),
+                        &mut HashSet::new())?;
+    Ok(component_masses)
 }
 
 // DFS on the component-ingredient graph
 //  - use to get the cell expressions that represents the actual
 //    proportion of each component.
+//  - each edge parent -> child contributes (percentage of child in parent)
+//    / (percentage total of parent), the fraction of parent that is child
+//  - a component reached along several paths sums the product of each path
 //  - must be called on the root (MIX)
 fn component_mass_aux(
     current: &str,
-    components: &HashMap<String, DoughComponent>,
-    component_percentages: &HashMap<String, HashMap<String, CSVCell>>,
+    formula: &DoughFormula,
+    layout: &SheetLayout,
     component_masses: &mut HashMap<String, CellExpr>,
     ref_stack: &mut VecDeque<CellExpr>, // hold CellRefs along path to current
     visited: &mut HashSet<String>,
     on_path: &mut HashSet<String>,
-) -> () {
+) -> Result<(), FormulaError> {
+    let components = &formula.components;
 
     // obtain expression for current and insert to component_masses
     if current != MIX {
@@ -317,103 +1707,680 @@ fn component_mass_aux(
 
     // check for cycle and iterate over recursive calls to each parent component
     let component = &components[current];
-    for (ing_name, _) in &component.ingredients {
+    let c = formula.component_index(current);
+    let total_ref = cell_ref(layout.total_percent(c), true);
+    for ing_name in &component.ingredient_order {
         if components.contains_key(ing_name) {
             if on_path.contains(ing_name) {
-                panic!("Component may not be self referencing (directly or indirectly)");
+                return Err(FormulaError::Cycle {
+                    component: current.to_string(),
+                    ingredient: ing_name.to_string(),
+                    location: component.location,
+                });
             }
 
-            let ing_percent_pos = component_percentages[current][ing_name]
-                                                    .position
-                                                    .clone();
-
-            let ing_percent_ref = CellRef {
-                pos: ing_percent_pos,
-                fix_row: true,
-                fix_col: true
-            };
+            let ing_percent_pos = layout
+                .percent(c, formula.ingredient_index(ing_name))
+                .expect("the layout has a cell for every ingredient of a component");
 
-            let ing_ref = CellExpr::Ref(ing_percent_ref);
+            let ing_ref = CellExpr::BinaryOp(
+                BinOp::Div,
+                Box::new(CellExpr::Ref(cell_ref(ing_percent_pos, true))),
+                Box::new(CellExpr::Ref(total_ref.clone())),
+            );
             ref_stack.push_front(ing_ref);
-            component_mass_aux(current, components, component_percentages, component_masses, ref_stack, visited, on_path);
+            component_mass_aux(ing_name, formula, layout, component_masses, ref_stack, visited, on_path)?;
             ref_stack.pop_front();
         }
     }
-    if current == MIX && components.len() != visited.len() {
-        panic!("mix must reference all components directly or indirectly");
+    if current == MIX {
+        check_connected(components, visited)?;
     }
     on_path.remove(current);
+    Ok(())
+}
+
+// returns a FormulaError::Disconnected for the first (by name) component
+// that was not visited
+fn check_connected(
+    components: &HashMap<String, DoughComponent>,
+    visited: &HashSet<String>,
+) -> Result<(), FormulaError> {
+    let mut unvisited: Vec<&String> = components.keys().filter(|c| !visited.contains(*c)).collect();
+    unvisited.sort();
+    match unvisited.first() {
+        Some(comp_name) => Err(FormulaError::Disconnected {
+            component: comp_name.to_string(),
+            location: components[*comp_name].location,
+        }),
+        None => Ok(()),
+    }
 }
 
 // DFS on the component-ingredient graph
-//  - will panic on finding cycle => invalid formula
-//  - will panic if it does not visit all components
+//  - returns FormulaError::Cycle on finding cycle => invalid formula
+//  - returns FormulaError::Disconnected if it does not visit all components
 fn validate_structure(
     current: &str,
     components: &HashMap<String, DoughComponent>,
     visited: &mut HashSet<String>,
     on_path: &mut HashSet<String>,
-) {
+) -> Result<(), FormulaError> {
     visited.insert(current.to_string());
     on_path.insert(current.to_string());
     let comp = components
         .get(current)
         .expect("dfs: cannot call on non-component");
-    for (ing_name, _) in &comp.ingredients {
+    for ing_name in &comp.ingredient_order {
         if components.contains_key(ing_name) {
             if on_path.contains(ing_name) {
-                panic!("Component may not be self referencing (directly or indirectly)");
+                return Err(FormulaError::Cycle {
+                    component: current.to_string(),
+                    ingredient: ing_name.to_string(),
+                    location: comp.location,
+                });
             }
-            validate_structure(ing_name, components, visited, on_path);
+            validate_structure(ing_name, components, visited, on_path)?;
         }
     }
-    if current == MIX && components.len() != visited.len() {
-        panic!("mix must reference all components directly or indirectly");
+    if current == MIX {
+        check_connected(components, visited)?;
     }
     on_path.remove(current);
+    Ok(())
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_dialect::Rounding;
+
+    #[test]
+    fn test_simple() {
+        yaml_to_dough_formula(String::from("./test_valid_1.yaml")).unwrap();
+        yaml_to_dough_formula(String::from("./test_valid_branches.yaml")).unwrap();
+    }
+
+    #[test]
+    fn test_to_sheet() {
+        let formula = yaml_to_dough_formula(String::from("./test_valid_1.yaml")).unwrap();
+        let sheet = formula.to_sheet().unwrap();
+        let water = sheet
+            .iter()
+            .find(|c| matches!(&c.value, CellValue::Str(s) if s == "water"))
+            .unwrap();
+        assert_eq!(water.position.col, 0);
+        assert!(sheet
+            .windows(2)
+            .all(|w| (w[0].position.row, w[0].position.col) < (w[1].position.row, w[1].position.col)));
+
+        let mut buffer: Vec<u8> = Vec::new();
+        formula.write_csv(&mut buffer).unwrap();
+        assert_eq!(String::from_utf8(buffer).unwrap(), formula.to_csv().unwrap());
+    }
+
+    #[test]
+    fn test_component_share() {
+        let formula = yaml_to_dough_formula(String::from("./test_valid_1.yaml")).unwrap();
+        let csv = formula.to_csv().unwrap();
+        let share_row = csv.lines().find(|l| l.starts_with("component share")).unwrap();
+        assert_eq!(share_row, "component share,=($D$8/$D$9),,=100%,,,,,,,");
+
+        // stem_1 reaches mix through both branches
+        let formula = yaml_to_dough_formula(String::from("./test_valid_branches.yaml")).unwrap();
+        let sheet = formula.to_sheet().unwrap();
+        let shares = component_mass(&formula, &formula.sheet_layout()).unwrap();
+        assert_eq!(shares.len(), formula.components.len());
+        assert!(matches!(shares["stem_1"], CellExpr::BinaryOp(BinOp::Add, _, _)));
+        assert!(sheet.iter().any(|c| matches!(&c.value, CellValue::Str(s) if s == "component share")));
+    }
+
+    #[test]
+    fn test_overall_formula() {
+        let mut formula = yaml_to_dough_formula(String::from("./test_valid_1.yaml")).unwrap();
+        formula.set_dough_serving(dec!(1000)).unwrap();
+        formula.set_batch_size(dec!(1)).unwrap();
+        let sheet = formula.to_sheet().unwrap();
+        let row_of = |name: &str| {
+            sheet
+                .iter()
+                .find(|c| matches!(&c.value, CellValue::Str(s) if s == name))
+                .unwrap()
+                .position
+                .row
+        };
+        let cell_at = |row: u32, col: u32| {
+            sheet
+                .iter()
+                .find(|c| c.position.row == row && c.position.col == col)
+                .map(|c| c.value.to_string())
+        };
+        let water = row_of("water");
+        assert_eq!(
+            cell_at(water, 6).unwrap(),
+            "=(SUMPRODUCT($B6:$E6,$B$11:$E$11)/$G$11)"
+        );
+        assert_eq!(cell_at(water, 7).unwrap(), "=(G6*($G$11*$B$15))");
+        assert_eq!(cell_at(row_of("component flour"), 6).unwrap(), "=SUM($B$11:$E$11)");
+        // preferments are counted through their own ingredients
+        let poolish = formula.sheet_layout().overall_percent(formula.ingredient_index("poolish"));
+        assert_eq!(cell_at(poolish.row, poolish.col), None);
+        assert_eq!(cell_at(2, 1).unwrap(), "%");
+        assert_eq!(cell_at(row_of("total"), 1).unwrap(), "=SUM(B4:B8)");
+    }
+
+    #[test]
+    fn test_dough_mass() {
+        let mut formula = yaml_to_dough_formula(String::from("./test_valid_1.yaml")).unwrap();
+        assert_eq!(formula.dough_mass(), Some(dec!(1000)));
+        formula.set_loss(dec!(0.2)).unwrap();
+        assert_eq!(formula.dough_mass(), Some(dec!(1250)));
+        // invalid settings are refused and leave the formula as it was
+        assert!(matches!(formula.set_loss(dec!(1)), Err(FormulaError::InvalidField { .. })));
+        assert!(formula.set_batch_size(dec!(0)).is_err());
+        assert!(formula.set_dough_serving(dec!(-500)).is_err());
+        assert_eq!(formula.dough_mass(), Some(dec!(1250)));
+
+        let csv = formula.to_csv().unwrap();
+        assert!(csv.contains("dough serving,=500,"));
+        assert!(csv.contains("loss,=20%,"));
+        assert!(csv.contains("dough mass,=(($B$12*$B$13)/(1-$B$14)),"));
+        assert!(csv.contains("=(B4*(($B$10*$B$15)/$B$9))"));
+    }
+
+    #[test]
+    fn test_evaluated_masses() {
+        let formula = yaml_to_dough_formula(String::from("./test_valid_branches.yaml")).unwrap();
+        let sheet = formula.to_sheet().unwrap();
+        let layout = formula.sheet_layout();
+        let mut evaluator = Evaluator::new(&sheet);
+        let mix = formula.component_index(MIX);
+        let mass_at = |evaluator: &mut Evaluator, pos: Option<CellPosition>| {
+            evaluator.number(&pos.unwrap()).unwrap().round_dp(6)
+        };
 
+        // both the mix and the overall formula add up to the dough mass
+        assert_eq!(mass_at(&mut evaluator, layout.total_mass(mix)), dec!(1000));
+        assert_eq!(mass_at(&mut evaluator, layout.overall_total_mass()), dec!(1000));
 
-// BFS on the component-ingredient graph
-//  - use to obtain ordering of components
-fn bfs_components(components: &HashMap<String, DoughComponent>) -> Vec<String> {
-    let mut comp_order: Vec<String> = Vec::new();
-    let mut queue: VecDeque<&DoughComponent> = VecDeque::new();
-    comp_order.push(MIX.to_string());
-    queue.push_back(components.get(MIX).unwrap());
-    while !queue.is_empty() {
-        let comp = queue.pop_front().unwrap();
-        for (ing_name, _) in &comp.ingredients {
-            if components.contains_key(ing_name) && !comp_order.contains(ing_name) {
-                let next_comp = &components[ing_name];
-                comp_order.push(ing_name.to_string());
-                queue.push_back(&next_comp);
+        // mix: 450 flour, 500 water, 300 stem_2 => 1250
+        let water = formula.ingredient_index("water");
+        let stem_2 = formula.ingredient_index("stem_2");
+        assert_eq!(mass_at(&mut evaluator, layout.mass(mix, water)), dec!(400));
+        assert_eq!(mass_at(&mut evaluator, layout.mass(mix, stem_2)), dec!(240));
+        assert_eq!(mass_at(&mut evaluator, Some(layout.share(mix))), dec!(1));
+    }
+
+    #[test]
+    fn test_layouts() {
+        let mut formula = yaml_to_dough_formula(String::from("./test_valid_branches.yaml")).unwrap();
+        let masses = |formula: &DoughFormula| {
+            let sheet = formula.to_sheet().unwrap();
+            let layout = formula.sheet_layout();
+            let mut evaluator = Evaluator::new(&sheet);
+            let mut masses: Vec<Decimal> = Vec::new();
+            for c in 0..formula.component_order.len() {
+                masses.push(evaluator.number(&layout.total_mass(c).unwrap()).unwrap().round_dp(6));
             }
+            for i in 0..formula.ingredient_order.len() {
+                if let Ok(x) = evaluator.number(&layout.overall_mass(i).unwrap()) {
+                    masses.push(x.round_dp(6));
+                }
+            }
+            masses
+        };
+        let standard = masses(&formula);
+        for kind in [LayoutKind::Transposed, LayoutKind::Stacked] {
+            formula.set_layout(kind);
+            assert_eq!(masses(&formula), standard);
         }
+
+        // the stacked layout only lists the ingredients of each component
+        let sheet = formula.to_sheet().unwrap();
+        let labels = sheet
+            .iter()
+            .filter(|c| matches!(&c.value, CellValue::Str(s) if s == "water"))
+            .count();
+        let with_water = formula
+            .components
+            .values()
+            .filter(|comp| comp.ingredients.contains_key("water"))
+            .count();
+        assert_eq!(labels, with_water + 1);
     }
-    comp_order
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_ingredient_order() {
+        let mut formula = yaml_to_dough_formula(String::from("./test_valid_1.yaml")).unwrap();
+        assert_eq!(formula.component_order, vec!["poolish", "mix"]);
+        assert_eq!(
+            formula.ingredient_order,
+            vec!["all purpose flour", "whole wheat flour", "water", "yeast", "poolish"]
+        );
+        // of 1400g of mix: 649.5g water, 524.8g all purpose flour, 300g
+        // poolish, 224.8g whole wheat flour and 1g yeast
+        formula.sort_ingredients(IngredientOrder::Mass);
+        assert_eq!(
+            formula.ingredient_order,
+            vec!["water", "all purpose flour", "poolish", "whole wheat flour", "yeast"]
+        );
+        formula.sort_ingredients(IngredientOrder::FlourFirst);
+        assert_eq!(
+            formula.ingredient_order,
+            vec!["all purpose flour", "whole wheat flour", "water", "yeast", "poolish"]
+        );
+        let csv = formula.to_csv().unwrap();
+        for _ in 0..5 {
+            let reloaded = yaml_to_dough_formula(String::from("./test_valid_1.yaml")).unwrap();
+            assert_eq!(reloaded.to_csv().unwrap(), csv);
+        }
+    }
 
     #[test]
-    fn test_simple() {
-        yaml_to_dough_formula(String::from("./test_valid_1.yaml"));
-        yaml_to_dough_formula(String::from("./test_valid_branches.yaml"));
+    fn test_json_and_toml() {
+        let yaml = yaml_to_dough_formula(String::from("./test_valid_1.yaml")).unwrap();
+        let json = json_to_dough_formula(String::from("./test_valid_1.json")).unwrap();
+        let toml = toml_to_dough_formula(String::from("./test_valid_1.toml")).unwrap();
+        assert_eq!(json.to_csv().unwrap(), yaml.to_csv().unwrap());
+        assert_eq!(toml.to_csv().unwrap(), yaml.to_csv().unwrap());
+
+        let res = json_str_to_dough_formula("{\"name\": \"bread\",\n \"components\": 3}");
+        assert!(matches!(res, Err(FormulaError::Json { location, .. }) if location.line == 2));
+        let res = toml_str_to_dough_formula("name = \"bread\"\n[[components]]\nname = \"mix\"\ningredients = [[\"water\", 1]]\n");
+        assert!(matches!(res, Err(FormulaError::Toml { location, .. }) if location.line == 4));
+        let res = json_str_to_dough_formula(
+            r#"{"name": "bread", "components": [{"name": "mix", "ingredients": [["flour", -1, true]]}]}"#,
+        );
+        assert!(matches!(res, Err(FormulaError::InvalidField { field, .. }) if field == "mass"));
+        let res = json_str_to_dough_formula(
+            r#"{"name": "bread", "components": [{"name": "poolish", "ingredients": [["flour", 1, true]]}]}"#,
+        );
+        assert!(matches!(res, Err(FormulaError::MissingMix)));
+    }
+
+    #[test]
+    fn test_read_api() {
+        let formula = yaml_to_dough_formula(String::from("./test_valid_branches.yaml")).unwrap();
+        let order: Vec<&str> = formula.components().map(DoughComponent::name).collect();
+        assert_eq!(order, vec!["poolish", "stem_1", "branch_2", "branch_1", "stem_2", "mix"]);
+
+        let poolish = formula.component("poolish").unwrap();
+        let names: Vec<&str> = poolish.ingredients().map(|(name, _)| name).collect();
+        assert_eq!(names, vec!["all purpose flour", "whole wheat flour", "water", "yeast"]);
+        let water = poolish.ingredient("water").unwrap();
+        assert_eq!(water.percentage(), dec!(1));
+        assert!(!water.is_flour());
+        assert_eq!(poolish.total().round_dp(5), dec!(2.00667));
+        assert_eq!(poolish.location().line, 11);
+        assert!(poolish.ingredient("salt").is_none());
+        assert!(formula.component("salt").is_none());
+
+        assert!(formula.is_flour("whole wheat flour"));
+        assert!(formula.is_component("stem_2"));
+        assert!(!formula.is_component("water"));
+        assert_eq!(formula.ingredient_names().len(), 9);
+        assert_eq!(formula.dough_serving(), Some(dec!(500)));
+        assert_eq!(formula.batch_size(), dec!(2));
+        assert_eq!(formula.loss(), dec!(0));
+    }
+
+    #[test]
+    fn test_mass_units() {
+        let grams = "name: bread\n\
+                     dough_serving: 500\n\
+                     components:\n  \
+                       - name: mix\n    \
+                         ingredients:\n      \
+                           - [bread flour, 1000, true]\n      \
+                           - [water, 700, false]\n      \
+                           - [salt, 18, false]\n";
+        let mixed = grams
+            .replace("[bread flour, 1000, true]", "{name: bread flour, mass: 1, unit: kg, flour: true}")
+            .replace("[water, 700, false]", "{name: water, mass: 700, unit: ml}")
+            .replace("[salt, 18, false]", "{name: salt, mass: 0.0396832, unit: lb}");
+        let grams = yaml_str_to_dough_formula(grams).unwrap();
+        let mixed = yaml_str_to_dough_formula(&mixed).unwrap();
+        for ing_name in ["bread flour", "water", "salt"] {
+            assert_eq!(percentage(&mixed, MIX, ing_name), percentage(&grams, MIX, ing_name));
+        }
+
+        let mut formula = grams;
+        assert!(matches!(
+            formula.set_mass_unit(Unit::Millilitre),
+            Err(FormulaError::VolumeUnit { unit: Unit::Millilitre })
+        ));
+        formula.set_mass_unit(Unit::Kilogram).unwrap();
+        let csv = formula.to_csv().unwrap();
+        assert!(csv.contains(",%,kg,"));
+        assert!(csv.contains("dough serving,=500,g,"));
+        assert!(csv.contains("dough mass,=((($B$10*$B$11)/(1-$B$12))/1000),kg,"));
+        let imported = csv_str_to_dough_formula(&csv).unwrap();
+        assert_eq!(imported.dough_serving(), Some(dec!(500)));
+        assert_eq!(imported.mass_unit(), Unit::Kilogram);
+        // a serving that does not convert to pounds exactly reads back as is
+        formula.set_mass_unit(Unit::Pound).unwrap();
+        let imported = csv_str_to_dough_formula(&formula.to_csv().unwrap()).unwrap();
+        assert_eq!(imported.dough_serving(), Some(dec!(500)));
+        assert_eq!(imported.to_yaml(), formula.to_yaml());
+    }
+
+    #[test]
+    fn test_precision() {
+        let source = "name: bread\n\
+                      components:\n  \
+                        - name: mix\n    \
+                          ingredients:\n      \
+                            - [bread flour, 100, true]\n      \
+                            - [water, 12.34567, false]\n";
+        let formula = yaml_str_to_dough_formula(source).unwrap();
+        assert_eq!(percentage(&formula, MIX, "water"), dec!(0.12346));
+        assert!(formula.to_csv().unwrap().contains("water,=12.346%,"));
+        let dialect = CsvDialect {
+            precision: Precision { decimals: 1, rounding: Rounding::Down },
+            ..CsvDialect::default()
+        };
+        assert!(formula.to_csv_with_dialect(&dialect).unwrap().contains("water,=12.3%,"));
+    }
+
+    #[test]
+    fn test_categories() {
+        let source = "name: brioche\n\
+                      components:\n  \
+                        - name: mix\n    \
+                          ingredients:\n      \
+                            - [bread flour, 1000, true]\n      \
+                            - [butter, 500, false]\n      \
+                            - {name: raisins, mass: 200, category: inclusion}\n      \
+                            - [water, 100, false]\n      \
+                            - [eggs, 400, false]\n      \
+                            - [milk, 100, false]\n      \
+                            - {name: quail eggs, mass: 50, category: egg}\n";
+        let mut formula = yaml_str_to_dough_formula(source).unwrap();
+        assert_eq!(formula.category("butter"), Some(Category::Fat));
+        assert_eq!(formula.category("raisins"), Some(Category::Inclusion));
+        assert_eq!(formula.category("mix"), None);
+        let csv = formula.to_csv().unwrap();
+        assert!(csv.contains("liquid subtotal,=(E7+E9),"));
+        assert!(csv.contains("fat subtotal,=E5,"));
+
+        formula.sort_ingredients(IngredientOrder::Category);
+        assert_eq!(
+            formula.ingredient_names(),
+            ["bread flour", "water", "milk", "eggs", "quail eggs", "butter", "raisins"]
+        );
+        let csv = formula.to_csv().unwrap();
+        assert!(csv.contains("liquid subtotal,=SUM(E5:E6),"));
+        // given categories survive a round trip through the sheet
+        let imported = csv_str_to_dough_formula(&csv).unwrap();
+        assert_eq!(imported.category("raisins"), Some(Category::Inclusion));
+        assert_eq!(imported.category("quail eggs"), Some(Category::Egg));
+        assert_eq!(imported.water_fraction("quail eggs"), Some(dec!(0.75)));
+        assert!(imported.to_yaml().contains("      - {name: quail eggs, mass: 5, flour: false, category: egg}\n"));
+        let res = csv_str_to_dough_formula(&csv.replace(",inclusion", ",seasoning"));
+        assert!(matches!(res, Err(FormulaError::InvalidField { field, .. }) if field == "category"));
+        let res = csv_str_to_dough_formula(&csv.replace(",inclusion", ",flour"));
+        assert!(matches!(res, Err(FormulaError::InvalidField { field, .. }) if field == "category"));
+        assert!(formula.to_yaml().contains("      - {name: raisins, mass: 20, flour: false, category: inclusion}\n"));
+        assert!(formula.to_yaml().contains("      - [butter, 50, false]\n"));
+
+        let conflict = "name: bread\n\
+                        components:\n  \
+                          - name: poolish\n    \
+                            ingredients:\n      \
+                              - [bread flour, 100, true]\n      \
+                              - {name: tea, mass: 100, category: liquid}\n  \
+                          - name: mix\n    \
+                            ingredients:\n      \
+                              - [bread flour, 100, true]\n      \
+                              - [poolish, 200, false]\n      \
+                              - [tea, 10, false]\n";
+        assert_eq!(yaml_str_to_dough_formula(conflict).unwrap().category("tea"), Some(Category::Liquid));
+        let res = yaml_str_to_dough_formula(&conflict.replace("[tea, 10, false]", "{name: tea, mass: 10, category: other}"));
+        assert!(matches!(res, Err(FormulaError::CategoryConflict { ingredient, .. }) if ingredient == "tea"));
+    }
+
+    #[test]
+    fn test_hydration() {
+        let source = "name: brioche\n\
+                      components:\n  \
+                        - name: sponge\n    \
+                          ingredients:\n      \
+                            - [bread flour, 200, true]\n      \
+                            - [milk, 200, false]\n  \
+                        - name: mix\n    \
+                          ingredients:\n      \
+                            - [bread flour, 800, true]\n      \
+                            - [sponge, 400, false]\n      \
+                            - [eggs, 400, false]\n      \
+                            - [butter, 500, false]\n      \
+                            - {name: water, mass: 50, water: 0.9}\n";
+        let mut formula = yaml_str_to_dough_formula(source).unwrap();
+        assert_eq!(formula.water_fraction("milk"), Some(dec!(0.87)));
+        assert_eq!(formula.water_fraction("water"), Some(dec!(0.9)));
+        assert_eq!(formula.water_fraction("sponge"), None);
+        // sponge: 174g of water in 200g of flour
+        assert_eq!(formula.component_hydration("sponge"), Some(dec!(0.87)));
+        // mix: 300g eggs, 80g butter and 45g of water in 800g of flour
+        assert_eq!(formula.component_hydration(MIX).unwrap().round_dp(6), dec!(0.53125));
+        // overall: 599g of water in 1000g of flour
+        assert_eq!(formula.hydration().round_dp(6), dec!(0.599));
+
+        for kind in [LayoutKind::Standard, LayoutKind::Transposed, LayoutKind::Stacked] {
+            formula.set_layout(kind);
+            let sheet = formula.to_sheet().unwrap();
+            let layout = formula.sheet_layout();
+            let mut evaluator = Evaluator::new(&sheet);
+            let mut hydration_at = |pos: CellPosition| evaluator.number(&pos).unwrap().round_dp(6);
+            assert_eq!(hydration_at(layout.hydration(0).1), dec!(0.87));
+            assert_eq!(hydration_at(layout.hydration(1).1), dec!(0.53125));
+            assert_eq!(hydration_at(layout.overall_hydration().1), dec!(0.599));
+        }
+
+        formula.set_layout(LayoutKind::Standard);
+        assert!(formula.to_yaml().contains("{name: water, mass: 6.25, flour: false, water: 0.9}"));
+        let imported = csv_str_to_dough_formula(&formula.to_csv().unwrap()).unwrap();
+        assert_eq!(imported.water_fraction("water"), Some(dec!(0.9)));
+        assert_eq!(imported.water_fraction("butter"), Some(dec!(0.16)));
+        assert_eq!(imported.hydration().round_dp(6), dec!(0.599));
+    }
+
+    #[test]
+    fn test_preferments() {
+        let source = "name: country loaf\n\
+                      components:\n  \
+                        - name: starter\n    \
+                          ingredients:\n      \
+                            - [bread flour, 50, true]\n      \
+                            - [water, 50, false]\n  \
+                        - name: levain\n    \
+                          ingredients:\n      \
+                            - [bread flour, 100, true]\n      \
+                            - [water, 100, false]\n      \
+                            - [starter, 20, false]\n  \
+                        - name: mix\n    \
+                          ingredients:\n      \
+                            - [bread flour, 880, true]\n      \
+                            - [water, 600, false]\n      \
+                            - [levain, 220, false]\n      \
+                            - [salt, 20, false]\n";
+        let mut formula = yaml_str_to_dough_formula(source).unwrap();
+        assert_eq!(formula.seeds(), vec![("starter", "levain"), ("levain", "mix")]);
+        // 10g of flour in the starter and 100g in the levain out of 990g
+        assert_eq!(formula.prefermented_flour("starter").unwrap().round_dp(6), dec!(0.010101));
+        assert_eq!(formula.prefermented_flour("levain").unwrap().round_dp(6), dec!(0.10101));
+        assert_eq!(formula.prefermented_flour(MIX), None);
+        assert_eq!(formula.total_prefermented_flour().round_dp(6), dec!(0.111111));
+        assert_eq!(formula.inoculation("starter", "levain"), Some(dec!(0.2)));
+        assert_eq!(formula.inoculation("levain", MIX), Some(dec!(0.25)));
+        assert_eq!(formula.inoculation("starter", MIX), None);
+
+        for kind in [LayoutKind::Standard, LayoutKind::Transposed, LayoutKind::Stacked] {
+            formula.set_layout(kind);
+            let sheet = formula.to_sheet().unwrap();
+            let layout = formula.sheet_layout();
+            let label_at = |pos: CellPosition| {
+                sheet.iter().find(|cell| cell.position == pos).map(|cell| cell.value.to_string())
+            };
+            assert_eq!(label_at(layout.prefermented_flour(1).0).as_deref(), Some("levain prefermented flour"));
+            assert_eq!(label_at(layout.inoculation(0).0).as_deref(), Some("starter inoculation of levain"));
+            let mut evaluator = Evaluator::new(&sheet);
+            let mut value_at = |pos: CellPosition| evaluator.number(&pos).unwrap().round_dp(6);
+            assert_eq!(value_at(layout.prefermented_flour(0).1), dec!(0.010101));
+            assert_eq!(value_at(layout.prefermented_flour(1).1), dec!(0.10101));
+            assert_eq!(value_at(layout.total_prefermented_flour().1), dec!(0.111111));
+            assert_eq!(value_at(layout.inoculation(0).1), dec!(0.2));
+            assert_eq!(value_at(layout.inoculation(1).1), dec!(0.25));
+        }
+
+        let straight = yaml_str_to_dough_formula(
+            "name: straight\ncomponents:\n  - name: mix\n    ingredients:\n      - [flour, 100, true]\n",
+        )
+        .unwrap();
+        assert_eq!(straight.total_prefermented_flour(), dec!(0));
+        assert!(straight.seeds().is_empty());
+        assert!(straight.to_csv().unwrap().contains("total prefermented flour,=0,"));
+    }
+
+    #[test]
+    fn test_yaml_str() {
+        let source = fs::read_to_string("./test_valid_1.yaml").unwrap();
+        let formula = yaml_str_to_dough_formula(&source).unwrap();
+        assert_eq!(formula.name(), "bread title");
+    }
+
+    // returns the percentage of ing_name in comp_name
+    fn percentage(formula: &DoughFormula, comp_name: &str, ing_name: &str) -> Decimal {
+        match formula.components[comp_name].ingredients[ing_name] {
+            Ingredient::Flour(x) | Ingredient::NonFlour(x) => x.round_dp(5),
+        }
+    }
+
+    #[test]
+    fn test_csv_round_trip() {
+        let mut formula = yaml_to_dough_formula(String::from("./test_valid_branches.yaml")).unwrap();
+        for kind in [LayoutKind::Standard, LayoutKind::Transposed, LayoutKind::Stacked] {
+            formula.set_layout(kind);
+            let imported = csv_str_to_dough_formula(&formula.to_csv().unwrap()).unwrap();
+            assert_eq!(imported.name(), formula.name());
+            assert_eq!(imported.layout(), kind);
+            assert_eq!(imported.component_order, formula.component_order);
+            assert_eq!(imported.ingredient_order, formula.ingredient_order);
+            assert_eq!(imported.flour, formula.flour);
+            assert_eq!(imported.dough_serving, formula.dough_serving);
+            assert_eq!(imported.batch_size, formula.batch_size);
+            for (comp_name, comp) in &formula.components {
+                for ing_name in comp.ingredients.keys() {
+                    assert_eq!(
+                        percentage(&imported, comp_name, ing_name),
+                        percentage(&formula, comp_name, ing_name)
+                    );
+                }
+            }
+            assert_eq!(imported.to_yaml(), formula.to_yaml());
+        }
+        // without a "%" title for its first component the layout is unknown
+        let csv = formula.to_csv().unwrap().replacen(",%,", ",percent,", 1);
+        assert!(matches!(csv_str_to_dough_formula(&csv), Err(FormulaError::UnknownLayout)));
+    }
+
+    #[test]
+    fn test_csv_edited_values() {
+        // a sheet saved with values instead of formulas, with the hydration
+        // of mix raised and the yeast removed from poolish
+        let csv = "bread title,,,,,,,,\n\
+                   ,poolish,,mix,,,overall,,\n\
+                   ,%,g,%,g,,%,g,\n\
+                   whole wheat flour,50.00%,150,25.00%,100,whole wheat flour,30%,200,flour\n\
+                   all purpose flour,50.00%,150,75.00%,300,all purpose flour,70%,450,flour\n\
+                   yeast,,,,,yeast,,,\n\
+                   water,100%,300,90%,360,water,90%,560,\n\
+                   poolish,,,50.00%,300,poolish,,,\n\
+                   total,200%,600,215%,860,,100%,1310,\n\
+                   loss,5%,,,,,,,\n";
+        let formula = csv_str_to_dough_formula(csv).unwrap();
+        assert_eq!(percentage(&formula, "mix", "water"), dec!(0.9));
+        assert!(!formula.components["poolish"].ingredients.contains_key("yeast"));
+        assert!(formula.flour.contains("all purpose flour"));
+        assert_eq!(formula.dough_serving, None);
+        assert_eq!(formula.loss, dec!(0.05));
+
+        let res = csv_str_to_dough_formula(&csv.replace("90%,360", "lots,360"));
+        match res {
+            Err(FormulaError::InvalidField { field, component, location, .. }) => {
+                assert_eq!(field, "percentage");
+                assert_eq!(component.as_deref(), Some("mix"));
+                assert_eq!(location, Location { line: 7, col: 4 });
+            }
+            _ => panic!("expected invalid percentage"),
+        }
+    }
+
+    #[test]
+    fn test_to_yaml() {
+        let formula = yaml_to_dough_formula(String::from("./test_valid_1.yaml")).unwrap();
+        let yaml = formula.to_yaml();
+        assert!(yaml.contains("  - name: mix\n"));
+        assert!(yaml.contains("      - [water, 83.333, false]\n"));
+        let reloaded = yaml_str_to_dough_formula(&yaml).unwrap();
+        assert_eq!(reloaded.to_yaml(), yaml);
+
+        let json = json_to_dough_formula(String::from("./test_valid_1.json")).unwrap();
+        let reloaded = json_str_to_dough_formula(&json.to_json()).unwrap();
+        let precision = Precision::default();
+        assert_eq!(reloaded.to_spec().round_masses(precision), json.to_spec().round_masses(precision));
+        // notes are kept, the unit is not as masses become percentages
+        let yeast = &reloaded.to_spec().components[0].ingredients[3];
+        assert_eq!(yeast.notes.as_deref(), Some("instant"));
+        assert!(json.to_yaml().contains("      - {name: yeast, mass: 0.667, flour: false, notes: instant}\n"));
     }
 
     #[test]
-    #[should_panic(expected = "self referencing")]
     fn test_cycle() {
-        yaml_to_dough_formula(String::from("./test_cycle.yaml"));
+        let res = yaml_to_dough_formula(String::from("./test_cycle.yaml"));
+        assert!(matches!(res, Err(FormulaError::Cycle { .. })));
     }
 
     #[test]
-    #[should_panic(expected = "mix must reference all components")]
     fn test_disconnected() {
-        yaml_to_dough_formula(String::from("./test_disconnected.yaml"));
+        let res = yaml_to_dough_formula(String::from("./test_disconnected.yaml"));
+        match res {
+            Err(FormulaError::Disconnected { component, location }) => {
+                assert_eq!(component, "deadend");
+                assert_eq!(location.line, 13);
+            }
+            _ => panic!("expected disconnected component"),
+        }
+    }
+
+    #[test]
+    fn test_invalid_mass() {
+        let res = yaml_to_dough_formula(String::from("./test_invalid_mass.yaml"));
+        match res {
+            Err(FormulaError::InvalidField { field, component, ingredient, location, .. }) => {
+                assert_eq!(field, "mass");
+                assert_eq!(component.as_deref(), Some("mix"));
+                assert_eq!(ingredient.as_deref(), Some("water"));
+                assert_eq!(location, Location { line: 7, col: 30 });
+            }
+            _ => panic!("expected invalid mass"),
+        }
+    }
+
+    #[test]
+    fn test_zero_flour() {
+        let res = yaml_to_dough_formula(String::from("./test_zero_flour.yaml"));
+        assert!(matches!(res, Err(FormulaError::ZeroFlour { component, .. }) if component == "soaker"));
+    }
+
+    #[test]
+    fn test_missing_mix() {
+        let res = yaml_to_dough_formula(String::from("./test_missing_mix.yaml"));
+        assert!(matches!(res, Err(FormulaError::MissingMix)));
+    }
+
+    #[test]
+    fn test_missing_file() {
+        let res = yaml_to_dough_formula(String::from("./does_not_exist.yaml"));
+        assert!(matches!(res, Err(FormulaError::Io { .. })));
     }
 }
@@ -0,0 +1,298 @@
+use crate::csv_cell::{CellArray, CellPosition, CellRef};
+use crate::unit::Unit;
+use rust_xlsxwriter::XlsxError;
+use std::{error, fmt, io};
+use zip::result::ZipError;
+
+// line and column (both starting at 1) of a node in the input file. The
+// default, line 0, stands for a formula that was not read from a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Location {
+    pub line: usize,
+    pub col: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.line == 0 {
+            write!(f, "an unknown location")
+        } else {
+            write!(f, "line {}, column {}", self.line, self.col)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum FormulaError {
+    // the input file could not be read
+    Io {
+        path: String,
+        source: io::Error,
+    },
+    // the generated sheet could not be written
+    Write {
+        source: io::Error,
+    },
+    // the xlsx workbook could not be built
+    Xlsx {
+        source: XlsxError,
+    },
+    // the ods document could not be built
+    Ods {
+        source: ZipError,
+    },
+    // the input is not valid yaml
+    Yaml {
+        message: String,
+        location: Location,
+    },
+    // the input is not valid json, or does not follow the formula schema
+    Json {
+        message: String,
+        location: Location,
+    },
+    // the input is not valid toml, or does not follow the formula schema
+    Toml {
+        message: String,
+        location: Location,
+    },
+    // a required field is absent. component is None for top level fields
+    MissingField {
+        field: String,
+        component: Option<String>,
+        location: Location,
+    },
+    // a field is present but holds the wrong kind of value
+    InvalidField {
+        field: String,
+        component: Option<String>,
+        ingredient: Option<String>,
+        expected: String,
+        location: Location,
+    },
+    DuplicateComponent {
+        component: String,
+        location: Location,
+    },
+    DuplicateIngredient {
+        component: String,
+        ingredient: String,
+        location: Location,
+    },
+    // an ingredient is flour in one component but not in another
+    FlourConflict {
+        component: String,
+        ingredient: String,
+        location: Location,
+    },
+    // an ingredient is given a different category in another component
+    CategoryConflict {
+        component: String,
+        ingredient: String,
+        location: Location,
+    },
+    // an ingredient is given a different water fraction in another component
+    WaterConflict {
+        component: String,
+        ingredient: String,
+        location: Location,
+    },
+    // a component without flour has no baker's percentages
+    ZeroFlour {
+        component: String,
+        location: Location,
+    },
+    MissingMix,
+    // the mass columns of a sheet were asked for in a volume unit
+    VolumeUnit {
+        unit: Unit,
+    },
+    // component references ingredient, which leads back to component
+    Cycle {
+        component: String,
+        ingredient: String,
+        location: Location,
+    },
+    // component is not reachable from mix
+    Disconnected {
+        component: String,
+        location: Location,
+    },
+    // an imported sheet has no "%" title where any layout puts that of the
+    // first component
+    UnknownLayout,
+    OverlappingCells {
+        position: CellPosition,
+    },
+    // from and to share neither a row nor a column, or to comes before from
+    InvalidCellArray {
+        from: CellRef,
+        to: CellRef,
+    },
+    MismatchedArrays {
+        left: CellArray,
+        right: CellArray,
+    },
+    // the separators of a csv dialect can not be told apart
+    InvalidDialect {
+        message: String,
+    },
+    // input could not be parsed, position is the byte offset of the error
+    Parse {
+        input: String,
+        position: usize,
+        message: String,
+    },
+    // the cell at position depends on its own value
+    CircularReference {
+        position: CellPosition,
+    },
+    DivisionByZero {
+        position: CellPosition,
+    },
+    // the cell at position does arithmetic on text
+    NotANumber {
+        position: CellPosition,
+    },
+    // the value of the cell at position does not fit in a Decimal
+    Overflow {
+        position: CellPosition,
+    },
+    // the cell at position sums a range of more than limit cells
+    RangeTooLarge {
+        position: CellPosition,
+        limit: u32,
+    },
+    // the cell at position is reached through a chain of more than limit
+    // formulas that each depend on the next
+    ReferenceChainTooLong {
+        position: CellPosition,
+        limit: usize,
+    },
+}
+
+impl fmt::Display for FormulaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormulaError::Io { path, source } => write!(f, "unable to read {}: {}", path, source),
+            FormulaError::Write { source } => write!(f, "unable to write sheet: {}", source),
+            FormulaError::Xlsx { source } => write!(f, "unable to build xlsx workbook: {}", source),
+            FormulaError::Ods { source } => write!(f, "unable to build ods document: {}", source),
+            FormulaError::Yaml { message, location } => {
+                write!(f, "invalid yaml at {}: {}", location, message)
+            }
+            FormulaError::Json { message, location } => {
+                write!(f, "invalid json at {}: {}", location, message)
+            }
+            FormulaError::Toml { message, location } => {
+                write!(f, "invalid toml at {}: {}", location, message)
+            }
+            FormulaError::MissingField { field, component, location } => match component {
+                Some(c) => write!(f, "component '{}' is missing field '{}' at {}", c, field, location),
+                None => write!(f, "formula is missing field '{}' at {}", field, location),
+            },
+            FormulaError::InvalidField { field, component, ingredient, expected, location } => {
+                write!(f, "field '{}'", field)?;
+                if let Some(i) = ingredient {
+                    write!(f, " of ingredient '{}'", i)?;
+                }
+                if let Some(c) = component {
+                    write!(f, " in component '{}'", c)?;
+                }
+                write!(f, " must be {} at {}", expected, location)
+            }
+            FormulaError::DuplicateComponent { component, location } => {
+                write!(f, "duplicate component '{}' at {}", component, location)
+            }
+            FormulaError::DuplicateIngredient { component, ingredient, location } => write!(
+                f,
+                "duplicate ingredient '{}' in component '{}' at {}",
+                ingredient, component, location
+            ),
+            FormulaError::FlourConflict { component, ingredient, location } => write!(
+                f,
+                "ingredient '{}' in component '{}' disagrees with another component on being flour at {}",
+                ingredient, component, location
+            ),
+            FormulaError::CategoryConflict { component, ingredient, location } => write!(
+                f,
+                "ingredient '{}' in component '{}' is given a different category than in another component at {}",
+                ingredient, component, location
+            ),
+            FormulaError::WaterConflict { component, ingredient, location } => write!(
+                f,
+                "ingredient '{}' in component '{}' is given a different water fraction than in another component at {}",
+                ingredient, component, location
+            ),
+            FormulaError::ZeroFlour { component, location } => {
+                write!(f, "component '{}' contains no flour at {}", component, location)
+            }
+            FormulaError::MissingMix => write!(f, "formula must have a component named 'mix'"),
+            FormulaError::VolumeUnit { unit } => {
+                write!(f, "masses can not be written in {}, which is a unit of volume", unit)
+            }
+            FormulaError::Cycle { component, ingredient, location } => write!(
+                f,
+                "component '{}' at {} is self referencing through '{}'",
+                component, location, ingredient
+            ),
+            FormulaError::Disconnected { component, location } => write!(
+                f,
+                "component '{}' at {} is not referenced by mix directly or indirectly",
+                component, location
+            ),
+            FormulaError::UnknownLayout => write!(
+                f,
+                "sheet is in no known layout, its first component has no '%' title"
+            ),
+            FormulaError::OverlappingCells { position } => {
+                write!(f, "csv grid contains overlapping cells at {}", position)
+            }
+            FormulaError::InvalidCellArray { from, to } => write!(
+                f,
+                "cell array {}:{} must span a single row or column from its first cell",
+                from, to
+            ),
+            FormulaError::MismatchedArrays { left, right } => {
+                write!(f, "cell arrays {} and {} differ in length", left, right)
+            }
+            FormulaError::InvalidDialect { message } => write!(f, "invalid csv dialect: {}", message),
+            FormulaError::Parse { input, position, message } => write!(
+                f,
+                "unable to parse '{}' at position {}: {}",
+                input, position, message
+            ),
+            FormulaError::CircularReference { position } => {
+                write!(f, "cell {} refers to itself directly or indirectly", position)
+            }
+            FormulaError::DivisionByZero { position } => {
+                write!(f, "cell {} divides by zero", position)
+            }
+            FormulaError::NotANumber { position } => {
+                write!(f, "cell {} does arithmetic on text", position)
+            }
+            FormulaError::Overflow { position } => {
+                write!(f, "cell {} overflows", position)
+            }
+            FormulaError::RangeTooLarge { position, limit } => {
+                write!(f, "cell {} sums a range of more than {} cells", position, limit)
+            }
+            FormulaError::ReferenceChainTooLong { position, limit } => write!(
+                f,
+                "cell {} is reached through a chain of more than {} formulas",
+                position, limit
+            ),
+        }
+    }
+}
+
+impl error::Error for FormulaError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            FormulaError::Io { source, .. } | FormulaError::Write { source } => Some(source),
+            FormulaError::Xlsx { source } => Some(source),
+            FormulaError::Ods { source } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,398 @@
+use crate::csv_cell::{BinOp, CSVCell, CellExpr, CellPosition, CellValue};
+use crate::error::FormulaError;
+use rust_decimal::Decimal;
+use std::collections::{HashMap, HashSet};
+
+// The result of evaluating a cell
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Number(Decimal),
+    Text(String),
+    Empty,
+}
+
+impl Value {
+    pub fn as_number(&self) -> Option<Decimal> {
+        match self {
+            Value::Number(x) => Some(*x),
+            _ => None,
+        }
+    }
+}
+
+// the most cells a SUM or SUMPRODUCT range may span, the number of rows of
+// a worksheet in common spreadsheet applications
+const MAX_RANGE_LEN: u32 = 1 << 20;
+// the most formulas that may depend on each other in a chain, which keeps
+// the recursive evaluation well within the stack
+const MAX_DEPTH: usize = 256;
+
+// Evaluates the cells of a sheet the way a spreadsheet application would.
+//  - empty cells count as 0 in arithmetic and are skipped by SUM
+//  - text is skipped by SUM and SUMPRODUCT but is an error in arithmetic
+//  - values are cached, so every cell is evaluated at most once
+//  - ranges longer than MAX_RANGE_LEN and chains of formulas deeper than
+//    MAX_DEPTH are errors rather than exhausting memory or the stack
+pub struct Evaluator<'a> {
+    cells: HashMap<CellPosition, &'a CellValue>,
+    values: HashMap<CellPosition, Value>,
+    in_progress: HashSet<CellPosition>,
+}
+
+impl<'a> Evaluator<'a> {
+    pub fn new(cells: &'a [CSVCell]) -> Self {
+        Evaluator {
+            cells: cells.iter().map(|c| (c.position.clone(), &c.value)).collect(),
+            values: HashMap::new(),
+            in_progress: HashSet::new(),
+        }
+    }
+
+    // returns the value of the cell at pos
+    pub fn value(&mut self, pos: &CellPosition) -> Result<Value, FormulaError> {
+        if let Some(value) = self.values.get(pos) {
+            return Ok(value.clone());
+        }
+        let cell: Option<&'a CellValue> = self.cells.get(pos).copied();
+        let value = match cell {
+            None | Some(CellValue::Empty) => Value::Empty,
+            Some(CellValue::Str(s)) => Value::Text(s.clone()),
+            Some(CellValue::Expr(expr)) => {
+                if self.in_progress.contains(pos) {
+                    return Err(FormulaError::CircularReference { position: pos.clone() });
+                }
+                if self.in_progress.len() >= MAX_DEPTH {
+                    return Err(FormulaError::ReferenceChainTooLong { position: pos.clone(), limit: MAX_DEPTH });
+                }
+                self.in_progress.insert(pos.clone());
+                let value = self.eval_expr(expr, pos);
+                self.in_progress.remove(pos);
+                value?
+            }
+        };
+        self.values.insert(pos.clone(), value.clone());
+        Ok(value)
+    }
+
+    // returns the value of the cell at pos, which must be a number
+    pub fn number(&mut self, pos: &CellPosition) -> Result<Decimal, FormulaError> {
+        match self.value(pos)? {
+            Value::Number(x) => Ok(x),
+            Value::Empty => Ok(Decimal::ZERO),
+            Value::Text(_) => Err(FormulaError::NotANumber { position: pos.clone() }),
+        }
+    }
+
+    // pos is the cell holding expr, used to report errors
+    fn eval_expr(&mut self, expr: &CellExpr, pos: &CellPosition) -> Result<Value, FormulaError> {
+        let overflow = || FormulaError::Overflow { position: pos.clone() };
+        match expr {
+            CellExpr::Number(x) | CellExpr::Percentage(x) => Ok(Value::Number(*x)),
+            CellExpr::Ref(cell_ref) => match self.value(&cell_ref.pos)? {
+                Value::Empty => Ok(Value::Number(Decimal::ZERO)),
+                value => Ok(value),
+            },
+            CellExpr::Sum(array) => {
+                check_range_len(array.len(), pos)?;
+                let mut total = Decimal::ZERO;
+                for p in array.positions() {
+                    if let Value::Number(x) = self.value(&p)? {
+                        total = total.checked_add(x).ok_or_else(overflow)?;
+                    }
+                }
+                Ok(Value::Number(total))
+            }
+            CellExpr::SumProduct(left, right) => {
+                check_range_len(left.len().max(right.len()), pos)?;
+                let mut total = Decimal::ZERO;
+                for (l, r) in left.positions().iter().zip(right.positions().iter()) {
+                    if let (Value::Number(x), Value::Number(y)) = (self.value(l)?, self.value(r)?) {
+                        let product = x.checked_mul(y).ok_or_else(overflow)?;
+                        total = total.checked_add(product).ok_or_else(overflow)?;
+                    }
+                }
+                Ok(Value::Number(total))
+            }
+            CellExpr::BinaryOp(op, left, right) => {
+                let x = self.eval_operand(left, pos)?;
+                let y = self.eval_operand(right, pos)?;
+                let result = match op {
+                    BinOp::Add => x.checked_add(y),
+                    BinOp::Sub => x.checked_sub(y),
+                    BinOp::Mult => x.checked_mul(y),
+                    BinOp::Div => {
+                        if y.is_zero() {
+                            return Err(FormulaError::DivisionByZero { position: pos.clone() });
+                        }
+                        x.checked_div(y)
+                    }
+                };
+                result.map(Value::Number).ok_or_else(overflow)
+            }
+        }
+    }
+
+    fn eval_operand(&mut self, expr: &CellExpr, pos: &CellPosition) -> Result<Decimal, FormulaError> {
+        match self.eval_expr(expr, pos)? {
+            Value::Number(x) => Ok(x),
+            Value::Empty => Ok(Decimal::ZERO),
+            Value::Text(_) => Err(FormulaError::NotANumber { position: pos.clone() }),
+        }
+    }
+}
+
+// pos is the cell holding a range of len cells, used to report errors
+fn check_range_len(len: u32, pos: &CellPosition) -> Result<(), FormulaError> {
+    if len > MAX_RANGE_LEN {
+        return Err(FormulaError::RangeTooLarge { position: pos.clone(), limit: MAX_RANGE_LEN });
+    }
+    Ok(())
+}
+
+// Returns the value of every cell in cells
+pub fn evaluate(cells: &[CSVCell]) -> Result<HashMap<CellPosition, Value>, FormulaError> {
+    let mut evaluator = Evaluator::new(cells);
+    let mut result: HashMap<CellPosition, Value> = HashMap::new();
+    for cell in cells {
+        let value = evaluator.value(&cell.position)?;
+        result.insert(cell.position.clone(), value);
+    }
+    Ok(result)
+}
+
+// Decides which cells hold a percentage. A formula is a percentage if every
+// value it combines is a percentage, such as the total of a percentage
+// column or a percentage divided by another. Multiplying by a plain number
+// such as the dough mass gives a mass instead
+pub(crate) struct PercentCells<'a> {
+    cells: HashMap<CellPosition, &'a CellValue>,
+    known: HashMap<CellPosition, bool>,
+}
+
+impl<'a> PercentCells<'a> {
+    pub(crate) fn new(cells: &'a [CSVCell]) -> Self {
+        PercentCells {
+            cells: cells.iter().map(|c| (c.position.clone(), &c.value)).collect(),
+            known: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn is_percent(&mut self, pos: &CellPosition) -> bool {
+        if let Some(known) = self.known.get(pos) {
+            return *known;
+        }
+        // a cell that refers to itself is not a percentage, the evaluator
+        // reports the cycle
+        self.known.insert(pos.clone(), false);
+        let percent = match self.cells.get(pos).copied() {
+            Some(CellValue::Expr(expr)) => self.is_percent_expr(expr),
+            _ => false,
+        };
+        self.known.insert(pos.clone(), percent);
+        percent
+    }
+
+    fn is_percent_expr(&mut self, expr: &CellExpr) -> bool {
+        match expr {
+            CellExpr::Percentage(_) => true,
+            CellExpr::Number(_) => false,
+            CellExpr::Ref(cell_ref) => self.is_percent(&cell_ref.pos),
+            CellExpr::Sum(array) => {
+                let positions: Vec<CellPosition> = array
+                    .positions()
+                    .into_iter()
+                    .filter(|p| matches!(self.cells.get(p), Some(CellValue::Expr(_))))
+                    .collect();
+                !positions.is_empty() && positions.iter().all(|p| self.is_percent(p))
+            }
+            // only pairs of two values add to the result
+            CellExpr::SumProduct(left, right) => {
+                let is_expr = |p: &CellPosition| matches!(self.cells.get(p), Some(CellValue::Expr(_)));
+                let pairs: Vec<(CellPosition, CellPosition)> = left
+                    .positions()
+                    .into_iter()
+                    .zip(right.positions())
+                    .filter(|(l, r)| is_expr(l) && is_expr(r))
+                    .collect();
+                !pairs.is_empty() && pairs.iter().all(|(l, r)| self.is_percent(l) && self.is_percent(r))
+            }
+            CellExpr::BinaryOp(_, left, right) => {
+                self.is_percent_expr(left) && self.is_percent_expr(right)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_cell::{CellArray, CellRef};
+    use rust_decimal_macros::dec;
+
+    fn pos(row: u32, col: u32) -> CellPosition {
+        CellPosition { row, col }
+    }
+
+    fn cell_ref(row: u32, col: u32) -> CellRef {
+        CellRef { pos: pos(row, col), fix_row: false, fix_col: false }
+    }
+
+    fn cell(row: u32, col: u32, expr: CellExpr) -> CSVCell {
+        CSVCell { value: CellValue::Expr(expr), position: pos(row, col) }
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let cells = vec![
+            cell(0, 0, CellExpr::Number(dec!(600))),
+            cell(1, 0, CellExpr::Percentage(dec!(0.75))),
+            cell(
+                2,
+                0,
+                CellExpr::BinaryOp(
+                    BinOp::Mult,
+                    Box::new(CellExpr::Ref(cell_ref(0, 0))),
+                    Box::new(CellExpr::Ref(cell_ref(1, 0))),
+                ),
+            ),
+            cell(3, 0, CellExpr::Sum(CellArray::new(cell_ref(0, 0), cell_ref(2, 0)).unwrap())),
+            cell(
+                0,
+                1,
+                CellExpr::sum_product(
+                    CellArray::new(cell_ref(0, 0), cell_ref(1, 0)).unwrap(),
+                    CellArray::new(cell_ref(4, 0), cell_ref(5, 0)).unwrap(),
+                )
+                .unwrap(),
+            ),
+            cell(4, 0, CellExpr::Number(dec!(2))),
+            cell(5, 0, CellExpr::Number(dec!(4))),
+        ];
+        let values = evaluate(&cells).unwrap();
+        assert_eq!(values[&pos(2, 0)], Value::Number(dec!(450)));
+        assert_eq!(values[&pos(3, 0)], Value::Number(dec!(1050.75)));
+        assert_eq!(values[&pos(0, 1)], Value::Number(dec!(1203)));
+    }
+
+    #[test]
+    fn test_text_and_empty() {
+        let cells = vec![
+            CSVCell { value: CellValue::Str(String::from("water")), position: pos(0, 0) },
+            cell(0, 1, CellExpr::Ref(cell_ref(0, 0))),
+            cell(1, 0, CellExpr::Sum(CellArray::new(cell_ref(0, 0), cell_ref(0, 5)).unwrap())),
+            cell(
+                2,
+                0,
+                CellExpr::BinaryOp(
+                    BinOp::Add,
+                    Box::new(CellExpr::Ref(cell_ref(9, 9))),
+                    Box::new(CellExpr::Number(dec!(1))),
+                ),
+            ),
+        ];
+        let mut evaluator = Evaluator::new(&cells);
+        assert_eq!(evaluator.value(&pos(0, 1)).unwrap(), Value::Text(String::from("water")));
+        assert_eq!(evaluator.number(&pos(1, 0)).unwrap(), dec!(0));
+        assert_eq!(evaluator.number(&pos(2, 0)).unwrap(), dec!(1));
+        assert!(matches!(evaluator.number(&pos(0, 1)), Err(FormulaError::NotANumber { .. })));
+    }
+
+    #[test]
+    fn test_circular_reference() {
+        let cells = vec![
+            cell(0, 0, CellExpr::Ref(cell_ref(1, 0))),
+            cell(1, 0, CellExpr::Sum(CellArray::new(cell_ref(0, 0), cell_ref(0, 1)).unwrap())),
+        ];
+        let res = evaluate(&cells);
+        assert!(matches!(res, Err(FormulaError::CircularReference { .. })));
+    }
+
+    #[test]
+    fn test_range_too_large() {
+        let cells = vec![
+            cell(0, 1, CellExpr::Sum(CellArray::new(cell_ref(0, 0), cell_ref(3_999_999_999, 0)).unwrap())),
+            cell(
+                1,
+                1,
+                CellExpr::sum_product(
+                    CellArray::new(cell_ref(0, 0), cell_ref(MAX_RANGE_LEN, 0)).unwrap(),
+                    CellArray::new(cell_ref(0, 2), cell_ref(MAX_RANGE_LEN, 2)).unwrap(),
+                )
+                .unwrap(),
+            ),
+        ];
+        let mut evaluator = Evaluator::new(&cells);
+        let res = evaluator.value(&pos(0, 1));
+        assert!(matches!(res, Err(FormulaError::RangeTooLarge { position, .. }) if position == pos(0, 1)));
+        let res = evaluator.value(&pos(1, 1));
+        assert!(matches!(res, Err(FormulaError::RangeTooLarge { position, .. }) if position == pos(1, 1)));
+    }
+
+    #[test]
+    fn test_reference_chain_too_long() {
+        // every cell of column 0 refers to the one below it
+        let chain = |len: u32| -> Vec<CSVCell> {
+            (0..len).map(|row| cell(row, 0, CellExpr::Ref(cell_ref(row + 1, 0)))).collect()
+        };
+        let cells = chain(100_000);
+        let res = Evaluator::new(&cells).value(&pos(0, 0));
+        let deepest = pos(MAX_DEPTH as u32, 0);
+        assert!(matches!(res, Err(FormulaError::ReferenceChainTooLong { position, .. }) if position == deepest));
+        let cells = chain(MAX_DEPTH as u32);
+        assert_eq!(Evaluator::new(&cells).number(&pos(0, 0)).unwrap(), dec!(0));
+    }
+
+    #[test]
+    fn test_percent_cells() {
+        let cells = vec![
+            cell(1, 1, CellExpr::Percentage(dec!(1))),
+            cell(2, 1, CellExpr::Percentage(dec!(0.75))),
+            cell(3, 1, CellExpr::Sum(CellArray::new(cell_ref(1, 1), cell_ref(2, 1)).unwrap())),
+            cell(4, 1, CellExpr::Number(dec!(1000))),
+            cell(
+                1,
+                2,
+                CellExpr::BinaryOp(
+                    BinOp::Div,
+                    Box::new(CellExpr::BinaryOp(
+                        BinOp::Mult,
+                        Box::new(CellExpr::Ref(cell_ref(1, 1))),
+                        Box::new(CellExpr::Ref(cell_ref(4, 1))),
+                    )),
+                    Box::new(CellExpr::Ref(cell_ref(3, 1))),
+                ),
+            ),
+            // the mass in column 2 pairs with an empty cell and is left out
+            cell(
+                5,
+                1,
+                CellExpr::sum_product(
+                    CellArray::new(cell_ref(1, 1), cell_ref(1, 3)).unwrap(),
+                    CellArray::new(cell_ref(2, 1), cell_ref(2, 3)).unwrap(),
+                )
+                .unwrap(),
+            ),
+        ];
+        let mut percent_cells = PercentCells::new(&cells);
+        assert!(percent_cells.is_percent(&pos(2, 1)));
+        assert!(percent_cells.is_percent(&pos(3, 1)));
+        assert!(!percent_cells.is_percent(&pos(4, 1)));
+        assert!(!percent_cells.is_percent(&pos(1, 2)));
+        assert!(percent_cells.is_percent(&pos(5, 1)));
+    }
+
+    #[test]
+    fn test_division_by_zero() {
+        let cells = vec![cell(
+            0,
+            0,
+            CellExpr::BinaryOp(
+                BinOp::Div,
+                Box::new(CellExpr::Number(dec!(1))),
+                Box::new(CellExpr::Ref(cell_ref(3, 3))),
+            ),
+        )];
+        let res = evaluate(&cells);
+        assert!(matches!(res, Err(FormulaError::DivisionByZero { position }) if position == pos(0, 0)));
+    }
+}
@@ -0,0 +1,319 @@
+use crate::csv_cell::{BinOp, CellArray, CellExpr, CellRef};
+use crate::csv_dialect::CsvDialect;
+use crate::error::FormulaError;
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use std::str::FromStr;
+
+// Recursive descent parser for the formulas written by CellExpr's Display.
+//   expr    := term (('+' | '-') term)*
+//   term    := factor (('*' | '/') factor)*
+//   factor  := '-'? (number '%'? | ref | function | '(' expr ')')
+//   function:= SUM '(' range ')' | SUMPRODUCT '(' range ',' range ')'
+//   range   := ref ':' ref
+// A leading '=' is optional and whitespace between tokens is ignored. The
+// decimal separator and the ',' between arguments follow the csv dialect.
+struct ExprParser<'a> {
+    input: &'a str,
+    pos: usize,
+    decimal_separator: char,
+    argument_separator: char,
+}
+
+impl<'a> ExprParser<'a> {
+    fn error(&self, position: usize, message: &str) -> FormulaError {
+        FormulaError::Parse {
+            input: self.input.to_string(),
+            position,
+            message: message.to_string(),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.input[self.pos..];
+        self.pos += rest.len() - rest.trim_start().len();
+    }
+
+    // returns the next non whitespace character without consuming it
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.input[self.pos..].chars().next()
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), FormulaError> {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error(self.pos, &format!("expected '{}'", c)))
+        }
+    }
+
+    // consumes characters while pred holds and returns them
+    fn take_while(&mut self, pred: impl Fn(char) -> bool) -> &'a str {
+        let rest = &self.input[self.pos..];
+        let len = rest.find(|c: char| !pred(c)).unwrap_or(rest.len());
+        self.pos += len;
+        &rest[..len]
+    }
+
+    fn parse_formula(&mut self) -> Result<CellExpr, FormulaError> {
+        if self.peek() == Some('=') {
+            self.pos += 1;
+        }
+        let expr = self.parse_expr()?;
+        if self.peek().is_some() {
+            return Err(self.error(self.pos, "unexpected character"));
+        }
+        Ok(expr)
+    }
+
+    fn parse_expr(&mut self) -> Result<CellExpr, FormulaError> {
+        let mut left = self.parse_term()?;
+        loop {
+            let op = match self.peek() {
+                Some('+') => BinOp::Add,
+                Some('-') => BinOp::Sub,
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            let right = self.parse_term()?;
+            left = CellExpr::BinaryOp(op, Box::new(left), Box::new(right));
+        }
+    }
+
+    fn parse_term(&mut self) -> Result<CellExpr, FormulaError> {
+        let mut left = self.parse_factor()?;
+        loop {
+            let op = match self.peek() {
+                Some('*') => BinOp::Mult,
+                Some('/') => BinOp::Div,
+                _ => return Ok(left),
+            };
+            self.pos += 1;
+            let right = self.parse_factor()?;
+            left = CellExpr::BinaryOp(op, Box::new(left), Box::new(right));
+        }
+    }
+
+    fn parse_factor(&mut self) -> Result<CellExpr, FormulaError> {
+        match self.peek() {
+            Some('-') => {
+                self.pos += 1;
+                match self.parse_factor()? {
+                    CellExpr::Number(x) => Ok(CellExpr::Number(-x)),
+                    CellExpr::Percentage(x) => Ok(CellExpr::Percentage(-x)),
+                    expr => Ok(CellExpr::BinaryOp(
+                        BinOp::Mult,
+                        Box::new(CellExpr::Number(dec!(-1))),
+                        Box::new(expr),
+                    )),
+                }
+            }
+            Some('(') => {
+                self.pos += 1;
+                let expr = self.parse_expr()?;
+                self.expect(')')?;
+                Ok(expr)
+            }
+            Some(c) if c.is_ascii_digit() || c == self.decimal_separator => self.parse_number(),
+            Some(c) if c == '$' || c.is_ascii_alphabetic() => {
+                // a word directly followed by '(' is a function, otherwise a reference
+                let word_start = self.pos;
+                let word = self.take_while(|c| c.is_ascii_alphabetic());
+                if !word.is_empty() && self.input[self.pos..].starts_with('(') {
+                    self.parse_function(word, word_start)
+                } else {
+                    self.pos = word_start;
+                    let cell_ref = self.parse_ref()?;
+                    if self.peek() == Some(':') {
+                        return Err(self.error(self.pos, "ranges are only allowed as function arguments"));
+                    }
+                    Ok(CellExpr::Ref(cell_ref))
+                }
+            }
+            Some(_) => Err(self.error(self.pos, "expected a number, reference or '('")),
+            None => Err(self.error(self.pos, "unexpected end of formula")),
+        }
+    }
+
+    fn parse_number(&mut self) -> Result<CellExpr, FormulaError> {
+        let start = self.pos;
+        let decimal_separator = self.decimal_separator;
+        let digits = self.take_while(|c| c.is_ascii_digit() || c == decimal_separator);
+        let x = Decimal::from_str(&digits.replace(decimal_separator, "."))
+            .map_err(|_| self.error(start, "invalid number"))?;
+        if self.peek() == Some('%') {
+            self.pos += 1;
+            Ok(CellExpr::Percentage(x / dec!(100)))
+        } else {
+            Ok(CellExpr::Number(x))
+        }
+    }
+
+    fn parse_ref(&mut self) -> Result<CellRef, FormulaError> {
+        self.skip_whitespace();
+        let start = self.pos;
+        let text = self.take_while(|c| c == '$' || c.is_ascii_alphanumeric());
+        CellRef::from_str(text).map_err(|e| match e {
+            FormulaError::Parse { position, message, .. } => self.error(start + position, &message),
+            e => e,
+        })
+    }
+
+    fn parse_range(&mut self) -> Result<CellArray, FormulaError> {
+        self.skip_whitespace();
+        let start = self.pos;
+        let from = self.parse_ref()?;
+        self.expect(':')?;
+        let to = self.parse_ref()?;
+        CellArray::new(from, to).map_err(|_| self.error(start, "range must span a single row or column from its first cell"))
+    }
+
+    fn parse_function(&mut self, name: &str, start: usize) -> Result<CellExpr, FormulaError> {
+        self.expect('(')?;
+        let expr = match name.to_ascii_uppercase().as_str() {
+            "SUM" => CellExpr::Sum(self.parse_range()?),
+            "SUMPRODUCT" => {
+                let left = self.parse_range()?;
+                self.expect(self.argument_separator)?;
+                let right = self.parse_range()?;
+                CellExpr::sum_product(left, right)
+                    .map_err(|_| self.error(start, "SUMPRODUCT ranges differ in length"))?
+            }
+            _ => return Err(self.error(start, &format!("unknown function '{}'", name))),
+        };
+        self.expect(')')?;
+        Ok(expr)
+    }
+}
+
+impl CellExpr {
+    // Parses a formula written in a csv of dialect, such as
+    // "=SUMPRODUCT($B3:$B9;C3:C9)*12,5%" in the semicolon dialect
+    pub fn parse_with_dialect(s: &str, dialect: &CsvDialect) -> Result<Self, FormulaError> {
+        ExprParser {
+            input: s,
+            pos: 0,
+            decimal_separator: dialect.decimal_separator,
+            argument_separator: dialect.argument_separator,
+        }
+        .parse_formula()
+    }
+}
+
+// Parses a formula such as "=SUMPRODUCT($B3:$B9,C3:C9)*10%"
+impl FromStr for CellExpr {
+    type Err = FormulaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        CellExpr::parse_with_dialect(s, &CsvDialect::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_cell::CellPosition;
+
+    fn cell_ref(row: u32, col: u32, fixed: bool) -> CellRef {
+        CellRef {
+            pos: CellPosition { row, col },
+            fix_row: fixed,
+            fix_col: fixed,
+        }
+    }
+
+    fn parse_position(input: &str) -> usize {
+        match input.parse::<CellExpr>() {
+            Err(FormulaError::Parse { position, .. }) => position,
+            res => panic!("expected parse error for {}, got {:?}", input, res),
+        }
+    }
+
+    #[test]
+    fn test_precedence() {
+        let expr: CellExpr = "=1+2*B3-4/5".parse().unwrap();
+        assert_eq!(expr.to_string(), "((1+(2*B3))-(4/5))");
+        let expr: CellExpr = "(1+2)*B3".parse().unwrap();
+        assert_eq!(expr.to_string(), "((1+2)*B3)");
+        let expr: CellExpr = " = 1 - - 2 ".parse().unwrap();
+        assert_eq!(expr, CellExpr::BinaryOp(
+            BinOp::Sub,
+            Box::new(CellExpr::Number(dec!(1))),
+            Box::new(CellExpr::Number(dec!(-2))),
+        ));
+    }
+
+    #[test]
+    fn test_functions() {
+        let expr: CellExpr = "=SUMPRODUCT($B3:$B9,C3:C9)*10%".parse().unwrap();
+        let left = CellArray::new(
+            CellRef { pos: CellPosition { row: 2, col: 1 }, fix_row: false, fix_col: true },
+            CellRef { pos: CellPosition { row: 8, col: 1 }, fix_row: false, fix_col: true },
+        ).unwrap();
+        let right = CellArray::new(cell_ref(2, 2, false), cell_ref(8, 2, false)).unwrap();
+        assert_eq!(expr, CellExpr::BinaryOp(
+            BinOp::Mult,
+            Box::new(CellExpr::SumProduct(left, right)),
+            Box::new(CellExpr::Percentage(dec!(0.1))),
+        ));
+        let expr: CellExpr = "sum( $B$3 : $B$9 )".parse().unwrap();
+        assert_eq!(expr.to_string(), "SUM($B$3:$B$9)");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let array = CellArray::new(cell_ref(2, 27, true), cell_ref(9, 27, true)).unwrap();
+        let exprs = [
+            CellExpr::Number(dec!(12.5)),
+            CellExpr::Percentage(dec!(0.125)),
+            CellExpr::Ref(cell_ref(3, 4, false)),
+            CellExpr::Sum(array.clone()),
+            CellExpr::SumProduct(array.clone(), array),
+            CellExpr::BinaryOp(
+                BinOp::Div,
+                Box::new(CellExpr::BinaryOp(
+                    BinOp::Mult,
+                    Box::new(CellExpr::Ref(cell_ref(0, 0, true))),
+                    Box::new(CellExpr::Ref(cell_ref(1, 702, false))),
+                )),
+                Box::new(CellExpr::BinaryOp(
+                    BinOp::Sub,
+                    Box::new(CellExpr::Number(dec!(1))),
+                    Box::new(CellExpr::Percentage(dec!(0.02))),
+                )),
+            ),
+        ];
+        for expr in exprs {
+            let parsed: CellExpr = expr.to_string().parse().unwrap();
+            assert_eq!(parsed, expr);
+        }
+    }
+
+    #[test]
+    fn test_dialect() {
+        let dialect = CsvDialect::semicolon();
+        let expr = CellExpr::parse_with_dialect("=SUMPRODUCT(B3:B9;C3:C9)*12,5%", &dialect).unwrap();
+        assert_eq!(expr.to_string(), "(SUMPRODUCT(B3:B9,C3:C9)*12.5%)");
+        assert_eq!(expr.format_with(&dialect), "(SUMPRODUCT(B3:B9;C3:C9)*12,5%)");
+        assert!(CellExpr::parse_with_dialect("=SUMPRODUCT(B3:B9,C3:C9)", &dialect).is_err());
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(parse_position("=1+"), 3);
+        assert_eq!(parse_position("=(1+2"), 5);
+        assert_eq!(parse_position("=B3:B9"), 3);
+        assert_eq!(parse_position("=AVERAGE(B3:B9)"), 1);
+        assert_eq!(parse_position("=SUM(B3)"), 7);
+        assert_eq!(parse_position("=SUM(B3:C9)"), 5);
+        // reversed ranges
+        assert_eq!(parse_position("=SUM(B9:B3)"), 5);
+        assert_eq!(parse_position("=SUMPRODUCT(B9:B3,C3:C9)"), 12);
+        assert_eq!(parse_position("=SUMPRODUCT(B3:B9,D3:C3)"), 18);
+        assert_eq!(parse_position("=B0"), 2);
+        assert_eq!(parse_position("=1 2"), 3);
+        assert_eq!(parse_position("=1.2.3"), 1);
+    }
+}
@@ -0,0 +1,642 @@
+use crate::csv_cell::{CellArray, CellPosition, CellRef};
+use crate::error::FormulaError;
+
+// rows above the ingredients in the standard layout, the title, the
+// component names and the "%" and "g" column titles
+const HEADER_ROWS: u32 = 3;
+// columns (or rows when transposed) taken by a component, the percent and
+// the mass column. The mass column is left empty if the dough mass is not
+// known
+const COMPONENT_WIDTH: u32 = 2;
+
+// How the blocks of the sheet are arranged
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LayoutKind {
+    // components side by side with a percent and a mass column each and the
+    // ingredients down the rows, followed by the overall formula
+    #[default]
+    Standard,
+    // the standard layout with rows and columns swapped
+    Transposed,
+    // one table per component listing only its own ingredients, stacked on
+    // top of each other and followed by the overall formula
+    Stacked,
+}
+
+// The labelled input cells below the formula
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Input {
+    DoughServing,
+    BatchSize,
+    Loss,
+    DoughMass,
+}
+
+impl Input {
+    pub const ALL: [Input; 4] = [Input::DoughServing, Input::BatchSize, Input::Loss, Input::DoughMass];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Input::DoughServing => "dough serving",
+            Input::BatchSize => "batch size",
+            Input::Loss => "loss",
+            Input::DoughMass => "dough mass",
+        }
+    }
+}
+
+// Computes the position of every cell of the sheet. Components and
+// ingredients are referred to by their index in the component and
+// ingredient order of the formula. Every block of the sheet is placed here,
+// so that the cells that refer to each other always agree:
+//  - the header, with the formula name and a name cell per component
+//  - the ingredient labels
+//  - a percent column and, if the dough mass is known, a mass column per
+//    component, each with its total
+//  - the share and flour of each component
+//  - the overall formula with its percent and mass columns, the total flour
+//    and the flour markers, water fractions and categories
+//  - the inputs
+//  - the hydration of every component and of the overall formula
+//  - the prefermented flour of every preferment and their total
+//  - the inoculation of every component that seeds another
+//  - the category subtotals of the overall formula
+#[derive(Debug, Clone)]
+pub struct SheetLayout {
+    kind: LayoutKind,
+    has_mass: bool,
+    num_ingredients: u32,
+    // ingredients of each component, in ingredient order
+    component_ingredients: Vec<Vec<usize>>,
+    // first row of the table of each component, used by the stacked layout
+    block_rows: Vec<u32>,
+    // components used in another component, counted once per user
+    num_seeds: u32,
+}
+
+impl SheetLayout {
+    pub fn new(
+        kind: LayoutKind,
+        has_mass: bool,
+        num_ingredients: usize,
+        component_ingredients: Vec<Vec<usize>>,
+        num_seeds: usize,
+    ) -> Self {
+        let mut block_rows: Vec<u32> = Vec::new();
+        let mut row = 1;
+        for ingredients in &component_ingredients {
+            block_rows.push(row);
+            // header, ingredients, totals, share, flour and a blank row
+            row += ingredients.len() as u32 + 5;
+        }
+        block_rows.push(row);
+        SheetLayout {
+            kind,
+            has_mass,
+            num_ingredients: num_ingredients as u32,
+            component_ingredients,
+            block_rows,
+            num_seeds: num_seeds as u32,
+        }
+    }
+
+    pub fn kind(&self) -> LayoutKind {
+        self.kind
+    }
+
+    pub fn has_mass(&self) -> bool {
+        self.has_mass
+    }
+
+    fn num_components(&self) -> u32 {
+        self.component_ingredients.len() as u32
+    }
+
+    // swaps row and col in the transposed layout
+    fn place(&self, row: u32, col: u32) -> CellPosition {
+        match self.kind {
+            LayoutKind::Transposed => CellPosition { row: col, col: row },
+            _ => CellPosition { row, col },
+        }
+    }
+
+    // row of ingredient in the table of component, stacked layout only
+    fn block_row(&self, component: usize, ingredient: usize) -> Option<u32> {
+        self.component_ingredients[component]
+            .iter()
+            .position(|i| *i == ingredient)
+            .map(|j| self.block_rows[component] + 1 + j as u32)
+    }
+
+    // row of the totals of component, stacked layout only
+    fn block_totals_row(&self, component: usize) -> u32 {
+        self.block_rows[component] + 1 + self.component_ingredients[component].len() as u32
+    }
+
+    // first row of the overall formula in the stacked layout, which holds
+    // its header
+    fn overall_row(&self) -> u32 {
+        self.block_rows[self.component_ingredients.len()]
+    }
+
+    // row of the component totals, in standard orientation
+    fn totals_row(&self) -> u32 {
+        HEADER_ROWS + self.num_ingredients
+    }
+
+    // column of the percentages of the overall formula, in standard
+    // orientation
+    fn overall_col(&self) -> u32 {
+        2 + COMPONENT_WIDTH * self.num_components()
+    }
+
+    pub fn title(&self) -> CellPosition {
+        CellPosition { row: 0, col: 0 }
+    }
+
+    pub fn component_header(&self, component: usize) -> CellPosition {
+        match self.kind {
+            LayoutKind::Stacked => CellPosition { row: self.block_rows[component], col: 0 },
+            _ => self.place(HEADER_ROWS - 2, 1 + COMPONENT_WIDTH * component as u32),
+        }
+    }
+
+    // the titles of the percent and mass column of component
+    pub fn component_titles(&self, component: usize) -> (CellPosition, CellPosition) {
+        let percent = match self.kind {
+            LayoutKind::Stacked => CellPosition { row: self.block_rows[component], col: 1 },
+            _ => self.place(HEADER_ROWS - 1, 1 + COMPONENT_WIDTH * component as u32),
+        };
+        let mass = self.next_to(&percent);
+        (percent, mass)
+    }
+
+    // the titles of the percent and mass column of the overall formula
+    pub fn overall_titles(&self) -> (CellPosition, CellPosition) {
+        let percent = match self.kind {
+            LayoutKind::Stacked => CellPosition { row: self.overall_row(), col: 1 },
+            _ => self.place(HEADER_ROWS - 1, self.overall_col()),
+        };
+        let mass = self.next_to(&percent);
+        (percent, mass)
+    }
+
+    pub fn overall_header(&self) -> CellPosition {
+        match self.kind {
+            LayoutKind::Stacked => CellPosition { row: self.overall_row(), col: 0 },
+            _ => self.place(HEADER_ROWS - 2, self.overall_col()),
+        }
+    }
+
+    // the single label of ingredient, which other cells refer to. The
+    // stacked layout has a label in every table instead
+    pub fn ingredient_label(&self, ingredient: usize) -> Option<CellPosition> {
+        match self.kind {
+            LayoutKind::Stacked => None,
+            _ => Some(self.place(HEADER_ROWS + ingredient as u32, 0)),
+        }
+    }
+
+    // every ingredient label cell with the index of its ingredient
+    pub fn ingredient_labels(&self) -> Vec<(usize, CellPosition)> {
+        match self.kind {
+            LayoutKind::Stacked => self
+                .component_ingredients
+                .iter()
+                .enumerate()
+                .flat_map(|(c, ingredients)| {
+                    ingredients.iter().map(move |i| (*i, CellPosition {
+                        row: self.block_row(c, *i).unwrap_or_default(),
+                        col: 0,
+                    }))
+                })
+                .collect(),
+            _ => (0..self.num_ingredients as usize)
+                .filter_map(|i| self.ingredient_label(i).map(|pos| (i, pos)))
+                .collect(),
+        }
+    }
+
+    // the percentage of ingredient in component. The stacked layout only
+    // has cells for the ingredients of the component
+    pub fn percent(&self, component: usize, ingredient: usize) -> Option<CellPosition> {
+        match self.kind {
+            LayoutKind::Stacked => self
+                .block_row(component, ingredient)
+                .map(|row| CellPosition { row, col: 1 }),
+            _ => Some(self.place(
+                HEADER_ROWS + ingredient as u32,
+                1 + COMPONENT_WIDTH * component as u32,
+            )),
+        }
+    }
+
+    // the mass of ingredient in component, next to its percentage
+    pub fn mass(&self, component: usize, ingredient: usize) -> Option<CellPosition> {
+        if !self.has_mass {
+            return None;
+        }
+        self.percent(component, ingredient)
+            .map(|pos| self.next_to(&pos))
+    }
+
+    // the cell after pos along the ingredient axis of the layout
+    fn next_to(&self, pos: &CellPosition) -> CellPosition {
+        match self.kind {
+            LayoutKind::Transposed => CellPosition { row: pos.row + 1, col: pos.col },
+            _ => CellPosition { row: pos.row, col: pos.col + 1 },
+        }
+    }
+
+    pub fn total_percent(&self, component: usize) -> CellPosition {
+        match self.kind {
+            LayoutKind::Stacked => CellPosition { row: self.block_totals_row(component), col: 1 },
+            _ => self.place(self.totals_row(), 1 + COMPONENT_WIDTH * component as u32),
+        }
+    }
+
+    pub fn total_mass(&self, component: usize) -> Option<CellPosition> {
+        self.has_mass
+            .then(|| self.next_to(&self.total_percent(component)))
+    }
+
+    // fraction of the total mass of mix that component makes up
+    pub fn share(&self, component: usize) -> CellPosition {
+        self.below_total(component, 1)
+    }
+
+    // flour of component as a fraction of the total mass of mix
+    pub fn flour(&self, component: usize) -> CellPosition {
+        self.below_total(component, 2)
+    }
+
+    // the cell offset rows below the percentage total of component
+    fn below_total(&self, component: usize, offset: u32) -> CellPosition {
+        let total = self.total_percent(component);
+        match self.kind {
+            LayoutKind::Transposed => CellPosition { row: total.row, col: total.col + offset },
+            _ => CellPosition { row: total.row + offset, col: total.col },
+        }
+    }
+
+    // labels of the rows of totals, of the components and of the overall
+    // formula
+    pub fn total_labels(&self) -> Vec<CellPosition> {
+        match self.kind {
+            LayoutKind::Stacked => {
+                let mut labels = self.row_labels(0);
+                labels.push(CellPosition { row: self.overall_total_percent().row, col: 0 });
+                labels
+            }
+            _ => self.row_labels(0),
+        }
+    }
+
+    // labels of the share and flour cells of the components
+    pub fn share_labels(&self) -> Vec<CellPosition> {
+        self.row_labels(1)
+    }
+
+    pub fn flour_labels(&self) -> Vec<CellPosition> {
+        self.row_labels(2)
+    }
+
+    fn row_labels(&self, offset: u32) -> Vec<CellPosition> {
+        match self.kind {
+            LayoutKind::Stacked => (0..self.component_ingredients.len())
+                .map(|c| CellPosition { row: self.block_totals_row(c) + offset, col: 0 })
+                .collect(),
+            _ => vec![self.place(self.totals_row() + offset, 0)],
+        }
+    }
+
+    // the label of ingredient in the overall formula
+    pub fn overall_label(&self, ingredient: usize) -> CellPosition {
+        match self.kind {
+            LayoutKind::Stacked => CellPosition { row: self.overall_row() + 1 + ingredient as u32, col: 0 },
+            _ => self.place(HEADER_ROWS + ingredient as u32, self.overall_col() - 1),
+        }
+    }
+
+    pub fn overall_percent(&self, ingredient: usize) -> CellPosition {
+        match self.kind {
+            LayoutKind::Stacked => CellPosition { row: self.overall_row() + 1 + ingredient as u32, col: 1 },
+            _ => self.place(HEADER_ROWS + ingredient as u32, self.overall_col()),
+        }
+    }
+
+    pub fn overall_mass(&self, ingredient: usize) -> Option<CellPosition> {
+        self.has_mass
+            .then(|| self.next_to(&self.overall_percent(ingredient)))
+    }
+
+    pub fn overall_total_percent(&self) -> CellPosition {
+        match self.kind {
+            LayoutKind::Stacked => CellPosition { row: self.overall_row() + 1 + self.num_ingredients, col: 1 },
+            _ => self.place(self.totals_row(), self.overall_col()),
+        }
+    }
+
+    pub fn overall_total_mass(&self) -> Option<CellPosition> {
+        self.has_mass
+            .then(|| self.next_to(&self.overall_total_percent()))
+    }
+
+    // the sum of the flour of all components, which is 100% of the overall
+    // formula
+    pub fn total_flour(&self) -> CellPosition {
+        match self.kind {
+            LayoutKind::Stacked => CellPosition { row: self.overall_row() + 2 + self.num_ingredients, col: 1 },
+            _ => self.place(self.totals_row() + 2, self.overall_col()),
+        }
+    }
+
+    // the label of the total flour, if it does not share the row of the
+    // component flour
+    pub fn total_flour_label(&self) -> Option<CellPosition> {
+        match self.kind {
+            LayoutKind::Stacked => Some(CellPosition { row: self.total_flour().row, col: 0 }),
+            _ => None,
+        }
+    }
+
+    // marks ingredient as flour, after the overall percentage and mass
+    pub fn flour_marker(&self, ingredient: usize) -> CellPosition {
+        let mut pos = self.overall_percent(ingredient);
+        for _ in 0..COMPONENT_WIDTH {
+            pos = self.next_to(&pos);
+        }
+        pos
+    }
+
+    // the fraction of ingredient that is water, after its flour marker
+    pub fn water_fraction(&self, ingredient: usize) -> CellPosition {
+        self.next_to(&self.flour_marker(ingredient))
+    }
+
+    // the title of the water fractions, in line with the overall titles
+    pub fn water_title(&self) -> CellPosition {
+        let (_, mass) = self.overall_titles();
+        self.next_to(&self.next_to(&mass))
+    }
+
+    // the category of ingredient, after its water fraction
+    pub fn category(&self, ingredient: usize) -> CellPosition {
+        self.next_to(&self.water_fraction(ingredient))
+    }
+
+    // the title of the categories, after that of the water fractions
+    pub fn category_title(&self) -> CellPosition {
+        self.next_to(&self.water_title())
+    }
+
+    // the water fractions of all ingredients
+    pub fn water_range(&self) -> Result<CellArray, FormulaError> {
+        range(
+            Some(self.water_fraction(0)),
+            Some(self.water_fraction(self.num_ingredients as usize - 1)),
+        )
+    }
+
+    // the label and value cell of input
+    pub fn input(&self, input: Input) -> (CellPosition, CellPosition) {
+        let first_row = match self.kind {
+            LayoutKind::Standard => self.totals_row() + 3,
+            // below the categories and a blank row
+            LayoutKind::Transposed => self.category(0).row + 2,
+            LayoutKind::Stacked => self.total_flour().row + 2,
+        };
+        let row = first_row + input as u32;
+        (CellPosition { row, col: 0 }, CellPosition { row, col: 1 })
+    }
+
+    // the unit of the value of input, after it
+    pub fn input_unit(&self, input: Input) -> CellPosition {
+        let (_, value) = self.input(input);
+        CellPosition { row: value.row, col: value.col + 1 }
+    }
+
+    // first row of the summaries below the inputs and a blank row, or in
+    // place of the inputs if there are none
+    fn summary_row(&self) -> u32 {
+        let (first, _) = self.input(Input::DoughServing);
+        if self.has_mass {
+            first.row + Input::ALL.len() as u32 + 1
+        } else {
+            first.row
+        }
+    }
+
+    // the label and value cell of the hydration of component
+    pub fn hydration(&self, component: usize) -> (CellPosition, CellPosition) {
+        let row = self.summary_row() + component as u32;
+        (CellPosition { row, col: 0 }, CellPosition { row, col: 1 })
+    }
+
+    // the label and value cell of the hydration of the overall formula,
+    // below that of the components
+    pub fn overall_hydration(&self) -> (CellPosition, CellPosition) {
+        self.hydration(self.component_ingredients.len())
+    }
+
+    // the label and value cell of the prefermented flour of the k-th
+    // preferment, below the hydration and a blank row. Every component but
+    // the mix is a preferment
+    pub fn prefermented_flour(&self, k: usize) -> (CellPosition, CellPosition) {
+        let row = self.overall_hydration().0.row + 2 + k as u32;
+        (CellPosition { row, col: 0 }, CellPosition { row, col: 1 })
+    }
+
+    // the label and value cell of the prefermented flour of all the
+    // preferments, below that of each one
+    pub fn total_prefermented_flour(&self) -> (CellPosition, CellPosition) {
+        self.prefermented_flour(self.component_ingredients.len() - 1)
+    }
+
+    // the label and value cell of the inoculation of the k-th seed, below
+    // the prefermented flour and a blank row
+    pub fn inoculation(&self, k: usize) -> (CellPosition, CellPosition) {
+        let row = self.total_prefermented_flour().0.row + 2 + k as u32;
+        (CellPosition { row, col: 0 }, CellPosition { row, col: 1 })
+    }
+
+    // the label, percentage and mass cell of the subtotal of the k-th
+    // category of the overall formula, below the inoculations and a blank
+    // row, or the prefermented flour if no component seeds another
+    pub fn category_subtotal(&self, k: usize) -> (CellPosition, CellPosition, Option<CellPosition>) {
+        let above = match self.num_seeds {
+            0 => self.total_prefermented_flour().0.row,
+            n => self.inoculation(n as usize - 1).0.row,
+        };
+        let row = above + 2 + k as u32;
+        (
+            CellPosition { row, col: 0 },
+            CellPosition { row, col: 1 },
+            self.has_mass.then_some(CellPosition { row, col: 2 }),
+        )
+    }
+
+    // the percentage cells of component, for its total
+    pub fn percent_range(&self, component: usize) -> Result<CellArray, FormulaError> {
+        let ingredients = &self.component_ingredients[component];
+        match self.kind {
+            LayoutKind::Stacked => {
+                let first = self.percent(component, ingredients[0]);
+                let last = self.percent(component, ingredients[ingredients.len() - 1]);
+                range(first, last)
+            }
+            _ => range(
+                self.percent(component, 0),
+                self.percent(component, self.num_ingredients as usize - 1),
+            ),
+        }
+    }
+
+    // the mass cells of component, next to its percentage cells
+    pub fn mass_range(&self, component: usize) -> Result<CellArray, FormulaError> {
+        let percent = self.percent_range(component)?;
+        range(
+            Some(self.next_to(&percent.start().pos)),
+            Some(self.next_to(&percent.end().pos)),
+        )
+    }
+
+    pub fn overall_percent_range(&self) -> Result<CellArray, FormulaError> {
+        range(
+            Some(self.overall_percent(0)),
+            Some(self.overall_percent(self.num_ingredients as usize - 1)),
+        )
+    }
+
+    pub fn overall_mass_range(&self) -> Result<CellArray, FormulaError> {
+        range(
+            self.overall_mass(0),
+            self.overall_mass(self.num_ingredients as usize - 1),
+        )
+    }
+
+    // the line of cells holding the flour of every component, in line with
+    // the cells of each ingredient. None if the layout has no such line
+    pub fn flour_line(&self) -> Option<(CellPosition, CellPosition)> {
+        match self.kind {
+            LayoutKind::Stacked => None,
+            _ => Some((self.flour(0), self.next_to(&self.flour(self.component_ingredients.len() - 1)))),
+        }
+    }
+
+    // the line of cells holding ingredient in every component, which is in
+    // line with the flour line
+    pub fn ingredient_line(&self, ingredient: usize) -> Option<(CellPosition, CellPosition)> {
+        let last = self.component_ingredients.len() - 1;
+        match (self.percent(0, ingredient), self.percent(last, ingredient)) {
+            (Some(first), Some(last)) if self.kind != LayoutKind::Stacked => {
+                Some((first, self.next_to(&last)))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn range(first: Option<CellPosition>, last: Option<CellPosition>) -> Result<CellArray, FormulaError> {
+    let to_ref = |pos: CellPosition| CellRef { pos, fix_row: false, fix_col: false };
+    match (first, last) {
+        (Some(first), Some(last)) => CellArray::new(to_ref(first), to_ref(last)),
+        _ => unreachable!("ranges are only taken over cells of the layout"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pos(row: u32, col: u32) -> CellPosition {
+        CellPosition { row, col }
+    }
+
+    // poolish holds ingredients 0, 1 and 3, mix holds all 5
+    fn layout(kind: LayoutKind) -> SheetLayout {
+        SheetLayout::new(kind, true, 5, vec![vec![0, 1, 3], vec![0, 1, 2, 3, 4]], 1)
+    }
+
+    #[test]
+    fn test_standard() {
+        let layout = layout(LayoutKind::Standard);
+        assert_eq!(layout.component_header(1), pos(1, 3));
+        assert_eq!(layout.component_titles(1), (pos(2, 3), pos(2, 4)));
+        assert_eq!(layout.percent(1, 2), Some(pos(5, 3)));
+        assert_eq!(layout.mass(1, 2), Some(pos(5, 4)));
+        assert_eq!(layout.total_percent(0), pos(8, 1));
+        assert_eq!(layout.total_labels(), vec![pos(8, 0)]);
+        assert_eq!(layout.flour(1), pos(10, 3));
+        assert_eq!(layout.overall_header(), pos(1, 6));
+        assert_eq!(layout.overall_label(0), pos(3, 5));
+        assert_eq!(layout.overall_percent(0), pos(3, 6));
+        assert_eq!(layout.total_flour(), pos(10, 6));
+        assert_eq!(layout.flour_marker(0), pos(3, 8));
+        assert_eq!(layout.input(Input::DoughMass), (pos(14, 0), pos(14, 1)));
+        assert_eq!(layout.water_fraction(0), pos(3, 9));
+        assert_eq!(layout.water_title(), pos(2, 9));
+        assert_eq!(layout.category(0), pos(3, 10));
+        assert_eq!(layout.category_title(), pos(2, 10));
+        assert_eq!(layout.hydration(1), (pos(17, 0), pos(17, 1)));
+        assert_eq!(layout.overall_hydration(), (pos(18, 0), pos(18, 1)));
+        assert_eq!(layout.prefermented_flour(0), (pos(20, 0), pos(20, 1)));
+        assert_eq!(layout.total_prefermented_flour(), (pos(21, 0), pos(21, 1)));
+        assert_eq!(layout.inoculation(0), (pos(23, 0), pos(23, 1)));
+        assert_eq!(layout.category_subtotal(1), (pos(26, 0), pos(26, 1), Some(pos(26, 2))));
+        assert_eq!(layout.ingredient_line(4), Some((pos(7, 1), pos(7, 4))));
+        assert_eq!(layout.flour_line(), Some((pos(10, 1), pos(10, 4))));
+        assert_eq!(layout.mass_range(0).unwrap().to_string(), "C4:C8");
+
+        let without_mass = SheetLayout::new(LayoutKind::Standard, false, 5, vec![vec![0], vec![0]], 0);
+        assert_eq!(without_mass.percent(1, 0), Some(pos(3, 3)));
+        assert_eq!(without_mass.mass(1, 0), None);
+        assert_eq!(without_mass.total_mass(1), None);
+        assert_eq!(without_mass.flour_marker(0), pos(3, 8));
+        assert_eq!(without_mass.hydration(0), (pos(11, 0), pos(11, 1)));
+        assert_eq!(without_mass.total_prefermented_flour(), (pos(16, 0), pos(16, 1)));
+        assert_eq!(without_mass.category_subtotal(0), (pos(18, 0), pos(18, 1), None));
+    }
+
+    #[test]
+    fn test_transposed() {
+        let standard = layout(LayoutKind::Standard);
+        let layout = layout(LayoutKind::Transposed);
+        assert_eq!(layout.component_header(1), pos(3, 1));
+        assert_eq!(layout.component_titles(1), (pos(3, 2), pos(4, 2)));
+        assert_eq!(layout.ingredient_label(2), Some(pos(0, 5)));
+        assert_eq!(layout.mass(1, 2), Some(pos(4, 5)));
+        assert_eq!(layout.total_flour(), pos(6, 10));
+        for i in 0..5 {
+            let flip = |p: CellPosition| pos(p.col, p.row);
+            assert_eq!(layout.percent(1, i), standard.percent(1, i).map(flip));
+            assert_eq!(layout.overall_percent(i), flip(standard.overall_percent(i)));
+            assert_eq!(layout.water_fraction(i), flip(standard.water_fraction(i)));
+            assert_eq!(layout.category(i), flip(standard.category(i)));
+        }
+        assert_eq!(layout.input(Input::DoughServing), (pos(12, 0), pos(12, 1)));
+    }
+
+    #[test]
+    fn test_stacked() {
+        let layout = layout(LayoutKind::Stacked);
+        assert_eq!(layout.component_header(0), pos(1, 0));
+        assert_eq!(layout.component_titles(0), (pos(1, 1), pos(1, 2)));
+        assert_eq!(layout.percent(0, 3), Some(pos(4, 1)));
+        assert_eq!(layout.percent(0, 2), None);
+        assert_eq!(layout.total_percent(0), pos(5, 1));
+        assert_eq!(layout.flour(0), pos(7, 1));
+        assert_eq!(layout.component_header(1), pos(9, 0));
+        assert_eq!(layout.percent_range(1).unwrap().to_string(), "B11:B15");
+        assert_eq!(layout.overall_header(), pos(19, 0));
+        assert_eq!(layout.total_labels(), vec![pos(5, 0), pos(15, 0), pos(25, 0)]);
+        assert_eq!(layout.overall_percent(4), pos(24, 1));
+        assert_eq!(layout.total_flour(), pos(26, 1));
+        assert_eq!(layout.flour_marker(0), pos(20, 3));
+        assert_eq!(layout.water_fraction(0), pos(20, 4));
+        assert_eq!(layout.water_title(), pos(19, 4));
+        assert_eq!(layout.category(0), pos(20, 5));
+        assert_eq!(layout.ingredient_line(0), None);
+        assert_eq!(layout.ingredient_labels().len(), 8);
+    }
+}
@@ -1,2 +1,13 @@
+pub mod category;
 pub mod csv_cell;
+pub mod csv_dialect;
 pub mod dough;
+pub mod error;
+pub mod eval;
+mod expr_parser;
+pub mod layout;
+mod marked_yaml;
+pub mod ods;
+pub mod schema;
+pub mod unit;
+pub mod xlsx;
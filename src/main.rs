@@ -1,17 +1,322 @@
-extern crate yaml_rust;
+use baking_formula::csv_dialect::{CsvDialect, Precision, QuoteEscape, Quoting, Rounding};
 use baking_formula::dough;
-use baking_formula::dough::DoughFormula;
-use std::fs;
+use baking_formula::dough::{DoughFormula, IngredientOrder};
+use baking_formula::error::FormulaError;
+use baking_formula::layout::LayoutKind;
+use baking_formula::unit::Unit;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use rust_decimal::Decimal;
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
 
 // ideas
 // 1. use mongodb to contain formulas
-// 2. allow for other types of input
-// 3. create simple front-end
-// 4. the data structure for Formula can be built recursively
-
-fn main() {
-    // read in data
-    let formula: DoughFormula =
-        dough::yaml_to_dough_formula("./test_valid_1.yaml".to_string());
-    // println!("{:#?}", formula);
+// 2. create simple front-end
+// 3. the data structure for Formula can be built recursively
+
+#[derive(Parser)]
+#[command(version, about = "Convert a yaml, json or toml baking formula into a spreadsheet")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Convert a formula into a spreadsheet
+    Convert {
+        input: PathBuf,
+        /// Output file (defaults to stdout)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        #[arg(short, long, value_enum, default_value_t = OutputFormat::Csv)]
+        format: OutputFormat,
+        /// Arrangement of the sheet
+        #[arg(long, value_enum, default_value_t = Layout::Standard)]
+        layout: Layout,
+        /// Order of the ingredient rows, components keep their source order
+        #[arg(long, value_enum, default_value_t = Order::Source)]
+        order: Order,
+        /// Unit of the mass columns
+        #[arg(short, long, value_enum, default_value_t = MassUnit::G)]
+        unit: MassUnit,
+        /// Mass of a single dough serving in grams, overrides dough_serving
+        #[arg(short, long)]
+        mass: Option<Decimal>,
+        /// Number of servings, overrides batch_size
+        #[arg(short, long)]
+        batch: Option<Decimal>,
+        /// Fraction of dough lost in production, overrides loss
+        #[arg(short, long)]
+        loss: Option<Decimal>,
+        #[command(flatten)]
+        dialect: DialectArgs,
+    },
+    /// Read an edited csv sheet back into a yaml formula
+    Import {
+        input: PathBuf,
+        /// Output file (defaults to stdout)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        #[arg(short, long, value_enum, default_value_t = FormulaFormat::Yaml)]
+        format: FormulaFormat,
+        #[command(flatten)]
+        dialect: DialectArgs,
+    },
+    /// Rewrite a formula with masses as baker's percentages
+    Normalize {
+        input: PathBuf,
+        /// Output file (defaults to stdout)
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        #[arg(short, long, value_enum, default_value_t = FormulaFormat::Yaml)]
+        format: FormulaFormat,
+    },
+    /// Check that a formula is well formed
+    Validate { input: PathBuf },
+    /// Print the baker's percentages of each component
+    Show { input: PathBuf },
+}
+
+// csv dialect options, RFC 4180 by default
+#[derive(Args)]
+struct DialectArgs {
+    /// Separator between csv fields
+    #[arg(long, default_value_t = ',')]
+    delimiter: char,
+    /// Decimal separator of numbers in formulas
+    #[arg(long, default_value_t = '.')]
+    decimal_separator: char,
+    /// Separator between function arguments in formulas
+    #[arg(long, default_value_t = ',')]
+    argument_separator: char,
+    /// Quote every field instead of only those that need it
+    #[arg(long)]
+    quote_all: bool,
+    /// Escape quotes with a backslash instead of doubling them
+    #[arg(long)]
+    backslash_escape: bool,
+    /// Decimals of numbers and percentages in formulas
+    #[arg(long, default_value_t = 3)]
+    decimals: u32,
+    /// How numbers are rounded to their decimals
+    #[arg(long, value_enum, default_value_t = RoundingMode::HalfEven)]
+    rounding: RoundingMode,
+}
+
+impl DialectArgs {
+    fn to_dialect(&self) -> CsvDialect {
+        CsvDialect {
+            delimiter: self.delimiter,
+            quoting: if self.quote_all { Quoting::Always } else { Quoting::Minimal },
+            escape: if self.backslash_escape {
+                QuoteEscape::Prefixed('\\')
+            } else {
+                QuoteEscape::Doubled
+            },
+            decimal_separator: self.decimal_separator,
+            argument_separator: self.argument_separator,
+            precision: Precision {
+                decimals: self.decimals,
+                rounding: self.rounding.into(),
+            },
+            ..CsvDialect::default()
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum RoundingMode {
+    /// To the nearest, halves to the even neighbour
+    HalfEven,
+    /// To the nearest, halves away from zero
+    HalfUp,
+    /// To the nearest, halves towards zero
+    HalfDown,
+    /// Towards zero
+    Down,
+    /// Away from zero
+    Up,
+}
+
+impl From<RoundingMode> for Rounding {
+    fn from(mode: RoundingMode) -> Self {
+        match mode {
+            RoundingMode::HalfEven => Rounding::HalfEven,
+            RoundingMode::HalfUp => Rounding::HalfUp,
+            RoundingMode::HalfDown => Rounding::HalfDown,
+            RoundingMode::Down => Rounding::Down,
+            RoundingMode::Up => Rounding::Up,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum OutputFormat {
+    Csv,
+    Xlsx,
+    Ods,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum FormulaFormat {
+    Yaml,
+    Json,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Layout {
+    /// Components side by side, ingredients down the rows
+    Standard,
+    /// Components down the rows, ingredients across the columns
+    Transposed,
+    /// One table per component, listing only its ingredients
+    Stacked,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Order {
+    /// As they first appear in the formula
+    Source,
+    /// Heaviest first
+    Mass,
+    /// Flour first, then the rest in source order
+    FlourFirst,
+    /// Grouped by category, components last
+    Category,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum MassUnit {
+    G,
+    Kg,
+    Oz,
+    Lb,
+}
+
+impl From<MassUnit> for Unit {
+    fn from(unit: MassUnit) -> Self {
+        match unit {
+            MassUnit::G => Unit::Gram,
+            MassUnit::Kg => Unit::Kilogram,
+            MassUnit::Oz => Unit::Ounce,
+            MassUnit::Lb => Unit::Pound,
+        }
+    }
+}
+
+impl From<Order> for IngredientOrder {
+    fn from(order: Order) -> Self {
+        match order {
+            Order::Source => IngredientOrder::Source,
+            Order::Mass => IngredientOrder::Mass,
+            Order::FlourFirst => IngredientOrder::FlourFirst,
+            Order::Category => IngredientOrder::Category,
+        }
+    }
+}
+
+impl From<Layout> for LayoutKind {
+    fn from(layout: Layout) -> Self {
+        match layout {
+            Layout::Standard => LayoutKind::Standard,
+            Layout::Transposed => LayoutKind::Transposed,
+            Layout::Stacked => LayoutKind::Stacked,
+        }
+    }
+}
+
+// reads a formula in the format given by the extension of input, yaml
+// unless it is json or toml
+fn load(input: &Path) -> Result<DoughFormula, FormulaError> {
+    let filename = input.to_string_lossy().to_string();
+    match input.extension().and_then(|e| e.to_str()) {
+        Some("json") => dough::json_to_dough_formula(filename),
+        Some("toml") => dough::toml_to_dough_formula(filename),
+        _ => dough::yaml_to_dough_formula(filename),
+    }
+}
+
+// returns a writer for path, or stdout if there is no path
+fn create_output(output: Option<PathBuf>) -> Result<Box<dyn Write>, Box<dyn Error>> {
+    Ok(match output {
+        Some(path) => Box::new(
+            File::create(&path)
+                .map_err(|e| format!("unable to create {}: {}", path.display(), e))?,
+        ),
+        None => Box::new(io::stdout().lock()),
+    })
+}
+
+fn write_formula(
+    formula: &DoughFormula,
+    output: Option<PathBuf>,
+    format: FormulaFormat,
+) -> Result<(), Box<dyn Error>> {
+    let text = match format {
+        FormulaFormat::Yaml => formula.to_yaml(),
+        FormulaFormat::Json => formula.to_json(),
+    };
+    create_output(output)?.write_all(text.as_bytes())?;
+    Ok(())
+}
+
+fn run(command: Command) -> Result<(), Box<dyn Error>> {
+    match command {
+        Command::Convert { input, output, format, layout, order, unit, mass, batch, loss, dialect } => {
+            let mut formula = load(&input)?;
+            formula.set_layout(layout.into());
+            formula.sort_ingredients(order.into());
+            formula.set_mass_unit(unit.into())?;
+            if let Some(mass) = mass {
+                formula.set_dough_serving(mass)?;
+            }
+            if let Some(batch) = batch {
+                formula.set_batch_size(batch)?;
+            }
+            if let Some(loss) = loss {
+                formula.set_loss(loss)?;
+            }
+            let writer = create_output(output)?;
+            match format {
+                OutputFormat::Csv => formula.write_csv_with_dialect(writer, &dialect.to_dialect())?,
+                OutputFormat::Xlsx => formula.write_xlsx(writer)?,
+                OutputFormat::Ods => formula.write_ods(writer)?,
+            }
+        }
+        Command::Import { input, output, format, dialect } => {
+            let formula = dough::csv_to_dough_formula_with_dialect(
+                input.to_string_lossy().to_string(),
+                &dialect.to_dialect(),
+            )?;
+            write_formula(&formula, output, format)?;
+        }
+        Command::Normalize { input, output, format } => {
+            write_formula(&load(&input)?, output, format)?;
+        }
+        Command::Validate { input } => {
+            let formula = load(&input)?;
+            println!("{}: '{}' is valid", input.display(), formula.name());
+        }
+        Command::Show { input } => {
+            print!("{}", load(&input)?);
+        }
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(cli.command) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
 }
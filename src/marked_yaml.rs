@@ -0,0 +1,153 @@
+use crate::error::{FormulaError, Location};
+use rust_decimal::Decimal;
+use std::{error, str::FromStr};
+use yaml_rust::{
+    parser::{Event, MarkedEventReceiver, Parser},
+    scanner::{Marker, TScalarStyle},
+    Yaml,
+};
+
+// A yaml document that remembers where each node starts in the source.
+// YamlLoader drops the markers reported by the parser, which leaves no way
+// to point a user at the offending line of a formula.
+#[derive(Debug)]
+pub enum NodeKind {
+    Scalar(Yaml),
+    Seq(Vec<Node>),
+    Map(Vec<(Node, Node)>),
+}
+
+#[derive(Debug)]
+pub struct Node {
+    pub kind: NodeKind,
+    pub location: Location,
+}
+
+impl Node {
+    // returns the value for key if node is a mapping containing key
+    pub fn get(&self, key: &str) -> Option<&Node> {
+        match &self.kind {
+            NodeKind::Map(entries) => entries
+                .iter()
+                .find(|(k, _)| k.as_str() == Some(key))
+                .map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match &self.kind {
+            NodeKind::Scalar(y) => y.as_str(),
+            _ => None,
+        }
+    }
+
+    // Integers are accepted wherever a real is expected. Reals are read from
+    // their text rather than through f64, so 0.1 stays exactly 0.1
+    pub fn as_decimal(&self) -> Option<Decimal> {
+        match &self.kind {
+            NodeKind::Scalar(Yaml::Integer(i)) => Some(Decimal::from(*i)),
+            NodeKind::Scalar(Yaml::Real(text)) => Decimal::from_str(text)
+                .or_else(|_| Decimal::from_scientific(text))
+                .ok(),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match &self.kind {
+            NodeKind::Scalar(y) => y.as_bool(),
+            _ => None,
+        }
+    }
+
+    pub fn as_vec(&self) -> Option<&Vec<Node>> {
+        match &self.kind {
+            NodeKind::Seq(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+fn to_location(marker: &Marker) -> Location {
+    Location {
+        line: marker.line(),
+        col: marker.col() + 1,
+    }
+}
+
+#[derive(Default)]
+struct NodeBuilder {
+    // partially built sequences and mappings. A mapping holds its pending key
+    stack: Vec<(Node, Option<Node>)>,
+    root: Option<Node>,
+}
+
+impl NodeBuilder {
+    fn insert(&mut self, node: Node) {
+        match self.stack.last_mut() {
+            None => {
+                if self.root.is_none() {
+                    self.root = Some(node);
+                }
+            }
+            Some((parent, pending_key)) => match &mut parent.kind {
+                NodeKind::Seq(items) => items.push(node),
+                NodeKind::Map(entries) => match pending_key.take() {
+                    Some(key) => entries.push((key, node)),
+                    None => *pending_key = Some(node),
+                },
+                NodeKind::Scalar(_) => unreachable!("scalars are never pushed on the stack"),
+            },
+        }
+    }
+}
+
+impl MarkedEventReceiver for NodeBuilder {
+    fn on_event(&mut self, ev: Event, mark: Marker) {
+        let location = to_location(&mark);
+        match ev {
+            Event::SequenceStart(_) => {
+                let node = Node { kind: NodeKind::Seq(Vec::new()), location };
+                self.stack.push((node, None));
+            }
+            Event::MappingStart(_) => {
+                let node = Node { kind: NodeKind::Map(Vec::new()), location };
+                self.stack.push((node, None));
+            }
+            Event::SequenceEnd | Event::MappingEnd => {
+                if let Some((node, _)) = self.stack.pop() {
+                    self.insert(node);
+                }
+            }
+            Event::Scalar(v, style, _, _) => {
+                let yaml = if style == TScalarStyle::Plain {
+                    Yaml::from_str(&v)
+                } else {
+                    Yaml::String(v)
+                };
+                self.insert(Node { kind: NodeKind::Scalar(yaml), location });
+            }
+            // aliases are not supported in formulas
+            Event::Alias(_) => self.insert(Node { kind: NodeKind::Scalar(Yaml::BadValue), location }),
+            _ => (),
+        }
+    }
+}
+
+// Parses the first document of source
+pub fn load(source: &str) -> Result<Node, FormulaError> {
+    let mut builder = NodeBuilder::default();
+    let mut parser = Parser::new(source.chars());
+    // ScanError only exposes its message without the location through the
+    // deprecated Error::description
+    #[allow(deprecated)]
+    parser.load(&mut builder, false).map_err(|e| FormulaError::Yaml {
+        message: error::Error::description(&e).to_string(),
+        location: to_location(e.marker()),
+    })?;
+    Ok(builder.root.unwrap_or(Node {
+        kind: NodeKind::Scalar(Yaml::Null),
+        location: Location { line: 1, col: 1 },
+    }))
+}
@@ -0,0 +1,268 @@
+use crate::csv_cell::{CSVCell, CellArray, CellExpr, CellValue};
+use crate::error::FormulaError;
+use crate::eval::{evaluate, PercentCells, Value};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{Cursor, Write},
+};
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+const MIMETYPE: &str = "application/vnd.oasis.opendocument.spreadsheet";
+const MIN_COL_WIDTH: usize = 10;
+// width of a character in the default font, in inches
+const CHAR_WIDTH: f64 = 0.08;
+
+const MANIFEST: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<manifest:manifest xmlns:manifest="urn:oasis:names:tc:opendocument:xmlns:manifest:1.0" manifest:version="1.2">
+ <manifest:file-entry manifest:full-path="/" manifest:version="1.2" manifest:media-type="application/vnd.oasis.opendocument.spreadsheet"/>
+ <manifest:file-entry manifest:full-path="content.xml" manifest:media-type="text/xml"/>
+</manifest:manifest>
+"#;
+
+// number and cell styles referenced by the cells of the table
+const STYLES: &str = r#"<office:automatic-styles>
+<number:percentage-style style:name="N-percent"><number:number number:decimal-places="1" number:min-integer-digits="1"/><number:text>%</number:text></number:percentage-style>
+<number:number-style style:name="N-mass"><number:number number:decimal-places="1" number:min-integer-digits="1"/></number:number-style>
+<style:style style:name="ce-header" style:family="table-cell"><style:text-properties fo:font-weight="bold"/></style:style>
+<style:style style:name="ce-percent" style:family="table-cell" style:data-style-name="N-percent"/>
+<style:style style:name="ce-mass" style:family="table-cell" style:data-style-name="N-mass"/>
+<style:style style:name="ce-percent-total" style:family="table-cell" style:data-style-name="N-percent"><style:table-cell-properties fo:border-top="0.06pt solid #000000"/><style:text-properties fo:font-weight="bold"/></style:style>
+<style:style style:name="ce-mass-total" style:family="table-cell" style:data-style-name="N-mass"><style:table-cell-properties fo:border-top="0.06pt solid #000000"/><style:text-properties fo:font-weight="bold"/></style:style>
+"#;
+
+// Returns the ods document for the given cells. The sheet is styled like the
+// xlsx workbook: formulas carry their evaluated value, percentages have a
+// percent format, SUM cells are totals and the header text is bold.
+// Formulas are written in OpenFormula syntax, "=SUM(B3:B9)" becomes
+// "of:=SUM([.B3:.B9])"
+pub fn cells_to_ods(cells: &[CSVCell]) -> Result<Vec<u8>, FormulaError> {
+    let content = content_xml(cells)?;
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    // the mimetype must be the first entry and stored uncompressed
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    let deflated = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    let entries = [
+        ("mimetype", MIMETYPE, stored),
+        ("META-INF/manifest.xml", MANIFEST, deflated),
+        ("content.xml", content.as_str(), deflated),
+    ];
+    for (name, data, options) in entries {
+        zip.start_file(name, options).map_err(to_formula_error)?;
+        zip.write_all(data.as_bytes())
+            .map_err(|e| FormulaError::Write { source: e })?;
+    }
+    Ok(zip.finish().map_err(to_formula_error)?.into_inner())
+}
+
+fn to_formula_error(e: zip::result::ZipError) -> FormulaError {
+    FormulaError::Ods { source: e }
+}
+
+// returns content.xml, which holds the single table of the document
+fn content_xml(cells: &[CSVCell]) -> Result<String, FormulaError> {
+    let values = evaluate(cells)?;
+    let mut percent_cells = PercentCells::new(cells);
+    let mut grid: BTreeMap<u32, BTreeMap<u32, &CSVCell>> = BTreeMap::new();
+    for cell in cells {
+        let row = grid.entry(cell.position.row).or_default();
+        if row.insert(cell.position.col, cell).is_some() {
+            return Err(FormulaError::OverlappingCells {
+                position: cell.position.clone(),
+            });
+        }
+    }
+    let header_end = cells
+        .iter()
+        .filter(|c| matches!(c.value, CellValue::Expr(_)))
+        .map(|c| c.position.row)
+        .min()
+        .unwrap_or(0);
+    let max_row = grid.keys().last().copied().unwrap_or(0);
+    let max_col = cells.iter().map(|c| c.position.col).max().unwrap_or(0);
+
+    let mut col_widths: HashMap<u32, usize> = HashMap::new();
+    for (pos, value) in &values {
+        if let Value::Text(s) = value {
+            let width = col_widths.entry(pos.col).or_insert(MIN_COL_WIDTH);
+            *width = (*width).max(s.chars().count() + 2);
+        }
+    }
+
+    let mut xml = String::from(concat!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>"#,
+        "\n",
+        r#"<office:document-content xmlns:office="urn:oasis:names:tc:opendocument:xmlns:office:1.0" "#,
+        r#"xmlns:style="urn:oasis:names:tc:opendocument:xmlns:style:1.0" "#,
+        r#"xmlns:text="urn:oasis:names:tc:opendocument:xmlns:text:1.0" "#,
+        r#"xmlns:table="urn:oasis:names:tc:opendocument:xmlns:table:1.0" "#,
+        r#"xmlns:number="urn:oasis:names:tc:opendocument:xmlns:datastyle:1.0" "#,
+        r#"xmlns:fo="urn:oasis:names:tc:opendocument:xmlns:xsl-fo-compatible:1.0" "#,
+        r#"xmlns:of="urn:oasis:names:tc:opendocument:xmlns:of:1.2" office:version="1.2">"#,
+        "\n"
+    ));
+    xml.push_str(STYLES);
+    for col in 0..=max_col {
+        let width = col_widths.get(&col).copied().unwrap_or(MIN_COL_WIDTH);
+        xml.push_str(&format!(
+            r#"<style:style style:name="co{}" style:family="table-column"><style:table-column-properties style:column-width="{:.3}in"/></style:style>"#,
+            col,
+            width as f64 * CHAR_WIDTH
+        ));
+        xml.push('\n');
+    }
+    xml.push_str("</office:automatic-styles>\n<office:body><office:spreadsheet><table:table table:name=\"Sheet1\">\n");
+    for col in 0..=max_col {
+        xml.push_str(&format!(r#"<table:table-column table:style-name="co{}"/>"#, col));
+    }
+    xml.push('\n');
+
+    for row in 0..=max_row {
+        xml.push_str("<table:table-row>");
+        let mut next_col = 0;
+        for (col, cell) in grid.get(&row).into_iter().flatten() {
+            if *col > next_col {
+                xml.push_str(&format!(
+                    r#"<table:table-cell table:number-columns-repeated="{}"/>"#,
+                    col - next_col
+                ));
+            }
+            next_col = col + 1;
+            let is_percent = percent_cells.is_percent(&cell.position);
+            xml.push_str(&cell_xml(cell, &values[&cell.position], is_percent, row < header_end));
+        }
+        if next_col == 0 {
+            xml.push_str("<table:table-cell/>");
+        }
+        xml.push_str("</table:table-row>\n");
+    }
+    xml.push_str("</table:table></office:spreadsheet></office:body></office:document-content>\n");
+    Ok(xml)
+}
+
+// returns the table:table-cell element of cell, which evaluated to value
+fn cell_xml(cell: &CSVCell, value: &Value, is_percent: bool, is_header: bool) -> String {
+    let expr = match &cell.value {
+        CellValue::Empty => return String::from("<table:table-cell/>"),
+        CellValue::Str(s) => {
+            let style = if is_header { r#" table:style-name="ce-header""# } else { "" };
+            return format!(
+                r#"<table:table-cell{} office:value-type="string"><text:p>{}</text:p></table:table-cell>"#,
+                style,
+                escape_xml(s)
+            );
+        }
+        CellValue::Expr(expr) => expr,
+    };
+    // plain numbers such as the batch size keep the default format
+    let style = match (expr, is_percent) {
+        (CellExpr::Number(_), _) => String::new(),
+        (CellExpr::Sum(_), true) => String::from(r#" table:style-name="ce-percent-total""#),
+        (CellExpr::Sum(_), false) => String::from(r#" table:style-name="ce-mass-total""#),
+        (_, true) => String::from(r#" table:style-name="ce-percent""#),
+        (_, false) => String::from(r#" table:style-name="ce-mass""#),
+    };
+    let formula = match expr {
+        CellExpr::Number(_) | CellExpr::Percentage(_) => String::new(),
+        _ => format!(r#" table:formula="of:={}""#, escape_xml(&open_formula(expr))),
+    };
+    let value = match value {
+        Value::Number(x) => {
+            let value_type = if is_percent { "percentage" } else { "float" };
+            format!(r#"office:value-type="{}" office:value="{}""#, value_type, x.normalize())
+        }
+        Value::Text(s) => format!(r#"office:value-type="string" office:string-value="{}""#, escape_xml(s)),
+        Value::Empty => String::from(r#"office:value-type="float" office:value="0""#),
+    };
+    format!(r#"<table:table-cell{}{} {}/>"#, style, formula, value)
+}
+
+// returns expr in OpenFormula syntax, where references are written in
+// brackets with a leading '.' for the current table and function arguments
+// are separated by ';'
+fn open_formula(expr: &CellExpr) -> String {
+    match expr {
+        CellExpr::BinaryOp(op, left, right) => {
+            format!("({}{}{})", open_formula(left), op, open_formula(right))
+        }
+        CellExpr::Ref(cell_ref) => format!("[.{}]", cell_ref),
+        CellExpr::Sum(array) => format!("SUM({})", open_range(array)),
+        CellExpr::SumProduct(left, right) => {
+            format!("SUMPRODUCT({};{})", open_range(left), open_range(right))
+        }
+        CellExpr::Number(_) | CellExpr::Percentage(_) => expr.to_string(),
+    }
+}
+
+fn open_range(array: &CellArray) -> String {
+    format!("[.{}:.{}]", array.start(), array.end())
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_cell::{BinOp, CellPosition, CellRef};
+    use rust_decimal_macros::dec;
+    use std::io::Read;
+
+    fn cell_ref(row: u32, col: u32, fixed: bool) -> CellRef {
+        CellRef { pos: CellPosition { row, col }, fix_row: fixed, fix_col: fixed }
+    }
+
+    fn cell(row: u32, col: u32, value: CellValue) -> CSVCell {
+        CSVCell { value, position: CellPosition { row, col } }
+    }
+
+    #[test]
+    fn test_open_formula() {
+        let array = CellArray::new(cell_ref(2, 1, false), cell_ref(8, 1, false)).unwrap();
+        let expr = CellExpr::BinaryOp(
+            BinOp::Div,
+            Box::new(CellExpr::SumProduct(array.clone(), array.clone())),
+            Box::new(CellExpr::BinaryOp(
+                BinOp::Mult,
+                Box::new(CellExpr::Ref(cell_ref(9, 6, true))),
+                Box::new(CellExpr::Percentage(dec!(0.5))),
+            )),
+        );
+        assert_eq!(
+            open_formula(&expr),
+            "(SUMPRODUCT([.B3:.B9];[.B3:.B9])/([.$G$10]*50%))"
+        );
+        assert_eq!(open_formula(&CellExpr::Sum(array)), "SUM([.B3:.B9])");
+    }
+
+    #[test]
+    fn test_cells_to_ods() {
+        let sum = CellArray::new(cell_ref(1, 1, false), cell_ref(2, 1, false)).unwrap();
+        let cells = vec![
+            cell(0, 1, CellValue::Str(String::from("flour & water"))),
+            cell(1, 1, CellValue::Expr(CellExpr::Percentage(dec!(1)))),
+            cell(2, 1, CellValue::Expr(CellExpr::Percentage(dec!(0.75)))),
+            cell(3, 1, CellValue::Expr(CellExpr::Sum(sum))),
+        ];
+        let buffer = cells_to_ods(&cells).unwrap();
+        assert_eq!(&buffer[30..38], b"mimetype");
+        let mut archive = zip::ZipArchive::new(Cursor::new(buffer)).unwrap();
+        let mut content = String::new();
+        archive
+            .by_name("content.xml")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert!(content.contains(concat!(
+            r#"<table:table-row><table:table-cell table:number-columns-repeated="1"/><table:table-cell table:style-name="ce-header" "#,
+            r#"office:value-type="string"><text:p>flour &amp; water</text:p></table:table-cell></table:table-row>"#
+        )));
+        assert!(content.contains(concat!(
+            r#"<table:table-cell table:style-name="ce-percent-total" table:formula="of:=SUM([.B2:.B3])" "#,
+            r#"office:value-type="percentage" office:value="1.75"/>"#
+        )));
+    }
+}
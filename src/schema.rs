@@ -0,0 +1,805 @@
+use crate::category::{self, Category};
+use crate::csv_dialect::Precision;
+use crate::error::{FormulaError, Location};
+use crate::marked_yaml::{self, Node, NodeKind};
+use crate::unit::{density, Quantity, Unit};
+use rust_decimal::{
+    prelude::ToPrimitive,
+    serde::{arbitrary_precision, arbitrary_precision_option},
+    Decimal,
+};
+use rust_decimal_macros::dec;
+use serde::{
+    de::{self, MapAccess, SeqAccess, Visitor},
+    Deserialize, Deserializer, Serialize,
+};
+use std::{borrow::Cow, fmt};
+use toml::de::{DeTable, DeValue};
+use yaml_rust::Yaml;
+
+// The newest version of the formula format. Files without a version are
+// read as version 1
+pub const SCHEMA_VERSION: u32 = 1;
+
+const INGREDIENT_FIELDS: [&str; 7] = ["name", "mass", "unit", "flour", "category", "water", "notes"];
+
+// A formula as written in a file, before percentages are computed and the
+// component graph is checked:
+//   version: 1
+//   name: bread
+//   dough_serving: 500   # optional, as are batch_size and loss
+//   components:
+//     - name: mix
+//       ingredients:
+//         - [bread flour, 1000, true]
+//         - {name: water, mass: 700, unit: ml, flour: false, notes: cold}
+// The mass of an ingredient is in grams unless a unit is given. A volume,
+// in ml or tsp, is turned into grams by the density of the ingredient. An
+// ingredient without a category is given one by its name, see
+// Category::infer. The water field is the fraction of the ingredient that
+// is water, which defaults to category::water_fraction
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FormulaSpec {
+    #[serde(default = "default_version")]
+    pub version: u32,
+    pub name: String,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "arbitrary_precision_option::serialize"
+    )]
+    pub dough_serving: Option<Decimal>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "arbitrary_precision_option::serialize"
+    )]
+    pub batch_size: Option<Decimal>,
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "arbitrary_precision_option::serialize"
+    )]
+    pub loss: Option<Decimal>,
+    pub components: Vec<ComponentSpec>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ComponentSpec {
+    pub name: String,
+    pub ingredients: Vec<IngredientSpec>,
+    #[serde(skip)]
+    pub location: Location,
+}
+
+// An ingredient, written either as the triple [name, mass, is_flour] or as
+// a mapping with named fields
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct IngredientSpec {
+    pub name: String,
+    #[serde(serialize_with = "arbitrary_precision::serialize")]
+    pub mass: Decimal,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unit: Option<Unit>,
+    pub flour: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub category: Option<Category>,
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "arbitrary_precision_option::serialize"
+    )]
+    pub water: Option<Decimal>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    #[serde(skip)]
+    pub location: Location,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct IngredientFields {
+    name: String,
+    mass: Decimal,
+    unit: Option<Unit>,
+    #[serde(default)]
+    flour: bool,
+    category: Option<Category>,
+    water: Option<Decimal>,
+    notes: Option<String>,
+}
+
+// reads either syntax of an ingredient, which an untagged enum would only
+// report as matching neither
+impl<'de> Deserialize<'de> for IngredientSpec {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(IngredientVisitor)
+    }
+}
+
+struct IngredientVisitor;
+
+impl<'de> Visitor<'de> for IngredientVisitor {
+    type Value = IngredientSpec;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an ingredient [name, mass, is_flour] or a mapping with name and mass")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let name: String = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(0, &self))?;
+        let mass: Decimal = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(1, &self))?;
+        let flour: bool = seq.next_element()?.ok_or_else(|| de::Error::invalid_length(2, &self))?;
+        if seq.next_element::<de::IgnoredAny>()?.is_some() {
+            return Err(de::Error::invalid_length(4, &self));
+        }
+        Ok(IngredientSpec::new(name, mass, flour))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+        let fields = IngredientFields::deserialize(de::value::MapAccessDeserializer::new(map))?;
+        Ok(IngredientSpec {
+            unit: fields.unit,
+            category: fields.category,
+            water: fields.water,
+            notes: fields.notes,
+            ..IngredientSpec::new(fields.name, fields.mass, fields.flour)
+        })
+    }
+}
+
+impl IngredientSpec {
+    pub fn new(name: String, mass: Decimal, flour: bool) -> Self {
+        IngredientSpec {
+            name,
+            mass,
+            unit: None,
+            flour,
+            category: None,
+            water: None,
+            notes: None,
+            location: Location::default(),
+        }
+    }
+
+    // the mass of the ingredient as written, in grams when no unit is given
+    pub fn quantity(&self) -> Quantity {
+        Quantity::new(self.mass, self.unit.unwrap_or_default())
+    }
+
+    // the category of the ingredient, as given or inferred
+    pub fn category(&self) -> Category {
+        self.category
+            .unwrap_or_else(|| Category::infer(&self.name, self.flour))
+    }
+
+    // the fraction of the ingredient that is water, as given or by default
+    pub fn water_fraction(&self) -> Decimal {
+        self.water
+            .unwrap_or_else(|| category::water_fraction(&self.name, self.category()))
+    }
+
+    // a given category must be flour exactly when the ingredient is
+    fn has_valid_category(&self) -> bool {
+        self.category
+            .is_none_or(|category| (category == Category::Flour) == self.flour)
+    }
+
+    // Returns the mass of the ingredient in grams, or None for a volume of
+    // an ingredient without a known density
+    pub fn grams(&self) -> Option<Decimal> {
+        self.quantity().to_grams(&self.name)
+    }
+
+    // the field and rule broken by an ingredient without grams: a volume of
+    // an ingredient of unknown density, or an amount too large to convert
+    pub(crate) fn grams_expected(&self) -> (&'static str, &'static str) {
+        let unit = self.unit.unwrap_or_default();
+        if unit.is_mass() || density(&self.name).is_some() {
+            ("mass", CONVERTIBLE_EXPECTED)
+        } else {
+            ("unit", DENSITY_EXPECTED)
+        }
+    }
+}
+
+fn default_version() -> u32 {
+    1
+}
+
+// the rules every value of a formula follows, with the text shown when one
+// is broken
+fn is_version(x: Decimal) -> bool {
+    x >= dec!(1) && x <= Decimal::from(SCHEMA_VERSION) && x.fract().is_zero()
+}
+const VERSION_EXPECTED: &str = "a supported version number";
+
+fn is_mass(x: Decimal) -> bool {
+    !x.is_sign_negative()
+}
+const MASS_EXPECTED: &str = "a non-negative number";
+
+pub(crate) fn is_positive(x: Decimal) -> bool {
+    x.is_sign_positive() && !x.is_zero()
+}
+pub(crate) const POSITIVE_EXPECTED: &str = "a positive number";
+
+pub(crate) fn is_loss(x: Decimal) -> bool {
+    !x.is_sign_negative() && x < dec!(1)
+}
+pub(crate) const LOSS_EXPECTED: &str = "a number from 0 up to but excluding 1";
+
+fn is_fraction(x: Decimal) -> bool {
+    !x.is_sign_negative() && x <= dec!(1)
+}
+const FRACTION_EXPECTED: &str = "a number from 0 to 1";
+
+const UNIT_EXPECTED: &str = "one of g, kg, oz, lb, ml and tsp";
+const CONVERTIBLE_EXPECTED: &str = "a number small enough to convert to grams";
+const DENSITY_EXPECTED: &str = "a mass unit, as the density of the ingredient is unknown";
+pub(crate) const CATEGORY_EXPECTED: &str = "one of flour, liquid, egg, fat, sugar, salt, leavening, inclusion and other";
+pub(crate) const FLOUR_CATEGORY_EXPECTED: &str = "flour exactly when the ingredient is flour";
+
+impl FormulaSpec {
+    // Parses a formula from yaml, reporting the location of the first value
+    // that does not follow the schema
+    pub fn from_yaml_str(source: &str) -> Result<Self, FormulaError> {
+        let doc = marked_yaml::load(source)?;
+
+        let version = optional_decimal(&doc, "version", VERSION_EXPECTED, is_version)?;
+        let name = required_field(&doc, "name", None)?;
+        let name = name
+            .as_str()
+            .ok_or_else(|| invalid_field("name", None, None, "a string", name.location))?
+            .to_string();
+        let dough_serving = optional_decimal(&doc, "dough_serving", POSITIVE_EXPECTED, is_positive)?;
+        let batch_size = optional_decimal(&doc, "batch_size", POSITIVE_EXPECTED, is_positive)?;
+        let loss = optional_decimal(&doc, "loss", LOSS_EXPECTED, is_loss)?;
+
+        let components = required_field(&doc, "components", None)?;
+        let components = components.as_vec().ok_or_else(|| {
+            invalid_field("components", None, None, "a list of components", components.location)
+        })?;
+        let mut component_specs: Vec<ComponentSpec> = Vec::new();
+        for s in components {
+            let seg_name = required_field(s, "name", None)?;
+            let seg_name = seg_name
+                .as_str()
+                .ok_or_else(|| invalid_field("name", None, None, "a string", seg_name.location))?
+                .to_string();
+            let ingredients = required_field(s, "ingredients", Some(&seg_name))?;
+            let ingredients = ingredients.as_vec().ok_or_else(|| {
+                invalid_field(
+                    "ingredients",
+                    Some(&seg_name),
+                    None,
+                    "a list of ingredients",
+                    ingredients.location,
+                )
+            })?;
+            let ingredients = ingredients
+                .iter()
+                .map(|ing| parse_ingredient(ing, &seg_name))
+                .collect::<Result<Vec<IngredientSpec>, FormulaError>>()?;
+            component_specs.push(ComponentSpec {
+                name: seg_name,
+                ingredients,
+                location: s.location,
+            });
+        }
+
+        Ok(FormulaSpec {
+            version: version.and_then(|v| v.to_u32()).unwrap_or_else(default_version),
+            name,
+            dough_serving,
+            batch_size,
+            loss,
+            components: component_specs,
+        })
+    }
+
+    // Parses a formula from json. Values are not checked, see validate
+    pub fn from_json_str(source: &str) -> Result<Self, FormulaError> {
+        serde_json::from_str(source).map_err(|e| {
+            let message = e.to_string();
+            // the message ends with the location, which is reported apart
+            let message = match message.rsplit_once(" at line ") {
+                Some((message, _)) => message.to_string(),
+                None => message,
+            };
+            FormulaError::Json {
+                message,
+                location: Location { line: e.line(), col: e.column() },
+            }
+        })
+    }
+
+    // Parses a formula from toml. Values are not checked, see validate.
+    // Floats are read from their source text, which f64 would round
+    pub fn from_toml_str(source: &str) -> Result<Self, FormulaError> {
+        let toml_error = |e: toml::de::Error| {
+            let mut message = e.message().to_string();
+            // floats are passed on as their text, errors about them name
+            // what the source holds
+            if let Some(text) = e.span().and_then(|span| source.get(span)) {
+                let text = text.replace('_', "");
+                message = message.replacen(&format!("string \"{}\"", text), &format!("floating point `{}`", text), 1);
+            }
+            FormulaError::Toml {
+                message,
+                location: e
+                    .span()
+                    .map_or(Location::default(), |span| location_at(source, span.start)),
+            }
+        };
+        let mut doc = DeTable::parse(source).map_err(toml_error)?;
+        for (_, value) in doc.get_mut().iter_mut() {
+            exact_toml_floats(value.get_mut());
+        }
+        FormulaSpec::deserialize(toml::de::Deserializer::from(doc)).map_err(toml_error)
+    }
+
+    // returns the formula with every mass rounded to precision
+    pub fn round_masses(mut self, precision: Precision) -> Self {
+        for comp in &mut self.components {
+            for ing in &mut comp.ingredients {
+                ing.mass = precision.round(ing.mass);
+            }
+        }
+        self
+    }
+
+    // Writes the formula in the yaml format read by from_yaml_str.
+    // Ingredients are triples unless they have fields beyond name, mass and
+    // flour
+    pub fn to_yaml(&self) -> String {
+        let mut yaml = String::new();
+        yaml.push_str(&format!("version: {}\n", self.version));
+        yaml.push_str(&format!("name: {}\n", yaml_string(&self.name)));
+        let numbers = [
+            ("dough_serving", self.dough_serving),
+            ("batch_size", self.batch_size),
+            ("loss", self.loss),
+        ];
+        for (field, x) in numbers {
+            if let Some(x) = x {
+                yaml.push_str(&format!("{}: {}\n", field, x.normalize()));
+            }
+        }
+        yaml.push_str("components:\n");
+        for comp in &self.components {
+            yaml.push_str(&format!("  - name: {}\n    ingredients:\n", yaml_string(&comp.name)));
+            for ing in &comp.ingredients {
+                let name = yaml_string(&ing.name);
+                let mass = ing.mass.normalize();
+                if ing.unit.is_none() && ing.category.is_none() && ing.water.is_none() && ing.notes.is_none() {
+                    yaml.push_str(&format!("      - [{}, {}, {}]\n", name, mass, ing.flour));
+                    continue;
+                }
+                let mut fields = vec![format!("name: {}", name), format!("mass: {}", mass)];
+                if let Some(unit) = &ing.unit {
+                    fields.push(format!("unit: {}", unit));
+                }
+                fields.push(format!("flour: {}", ing.flour));
+                if let Some(category) = &ing.category {
+                    fields.push(format!("category: {}", category));
+                }
+                if let Some(water) = ing.water {
+                    fields.push(format!("water: {}", water.normalize()));
+                }
+                if let Some(notes) = &ing.notes {
+                    fields.push(format!("notes: {}", yaml_string(notes)));
+                }
+                yaml.push_str(&format!("      - {{{}}}\n", fields.join(", ")));
+            }
+        }
+        yaml
+    }
+
+    // Writes the formula as pretty printed json, in the format read by
+    // from_json_str
+    pub fn to_json(&self) -> String {
+        let mut json = serde_json::to_string_pretty(self).expect("a formula only has string keys");
+        json.push('\n');
+        json
+    }
+
+    // Checks the values of a formula that was not read by from_yaml_str,
+    // which checks them as it reads them
+    pub fn validate(&self) -> Result<(), FormulaError> {
+        let check = |field: &str, x: Option<Decimal>, expected: &str, is_valid: fn(Decimal) -> bool| {
+            match x {
+                Some(x) if !is_valid(x) => Err(invalid_field(field, None, None, expected, Location::default())),
+                _ => Ok(()),
+            }
+        };
+        check("version", Some(Decimal::from(self.version)), VERSION_EXPECTED, is_version)?;
+        check("dough_serving", self.dough_serving, POSITIVE_EXPECTED, is_positive)?;
+        check("batch_size", self.batch_size, POSITIVE_EXPECTED, is_positive)?;
+        check("loss", self.loss, LOSS_EXPECTED, is_loss)?;
+        for comp in &self.components {
+            for ing in &comp.ingredients {
+                let invalid = |field: &str, expected: &str| {
+                    Err(invalid_field(field, Some(&comp.name), Some(&ing.name), expected, ing.location))
+                };
+                if !is_mass(ing.mass) {
+                    return invalid("mass", MASS_EXPECTED);
+                }
+                if ing.grams().is_none() {
+                    let (field, expected) = ing.grams_expected();
+                    return invalid(field, expected);
+                }
+                if !ing.has_valid_category() {
+                    return invalid("category", FLOUR_CATEGORY_EXPECTED);
+                }
+                if ing.water.is_some_and(|water| !is_fraction(water)) {
+                    return invalid("water", FRACTION_EXPECTED);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// returns s as a yaml scalar, quoted unless it reads back as the same string.
+// Yaml::from_str only tells the type of a plain scalar, so names starting
+// with an indicator, which would not read as a plain scalar at all, are
+// quoted up front
+fn yaml_string(s: &str) -> String {
+    let is_plain = !s.is_empty()
+        && s.trim() == s
+        && !s.starts_with(|c: char| "-?:,[]{}#&*!|>'\"%@`".contains(c))
+        && s.chars().all(|c| c.is_alphanumeric() || " _-.'()".contains(c))
+        && matches!(Yaml::from_str(s), Yaml::String(_));
+    if is_plain {
+        s.to_string()
+    } else {
+        format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+// replaces every float in value by its source text, without the digit
+// separators toml allows, which Decimal reads exactly
+fn exact_toml_floats(value: &mut DeValue) {
+    match value {
+        DeValue::Float(float) => {
+            let text = float.as_str().replace('_', "");
+            *value = DeValue::String(Cow::Owned(text));
+        }
+        DeValue::Array(array) => {
+            for item in array.iter_mut() {
+                exact_toml_floats(item.get_mut());
+            }
+        }
+        DeValue::Table(table) => {
+            for (_, item) in table.iter_mut() {
+                exact_toml_floats(item.get_mut());
+            }
+        }
+        _ => {}
+    }
+}
+
+// returns the line and column of the byte at offset in source
+fn location_at(source: &str, offset: usize) -> Location {
+    let before = &source[..offset.min(source.len())];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    Location {
+        line: before.matches('\n').count() + 1,
+        col: before[line_start..].chars().count() + 1,
+    }
+}
+
+// returns a FormulaError::InvalidField for the given field
+pub(crate) fn invalid_field(
+    field: &str,
+    component: Option<&str>,
+    ingredient: Option<&str>,
+    expected: &str,
+    location: Location,
+) -> FormulaError {
+    FormulaError::InvalidField {
+        field: field.to_string(),
+        component: component.map(str::to_string),
+        ingredient: ingredient.map(str::to_string),
+        expected: expected.to_string(),
+        location,
+    }
+}
+
+// returns the value of a required mapping field
+fn required_field<'a>(
+    node: &'a Node,
+    field: &str,
+    component: Option<&str>,
+) -> Result<&'a Node, FormulaError> {
+    node.get(field).ok_or_else(|| FormulaError::MissingField {
+        field: field.to_string(),
+        component: component.map(str::to_string),
+        location: node.location,
+    })
+}
+
+// returns the value of an optional number field, which must satisfy
+// is_valid
+fn optional_decimal(
+    doc: &Node,
+    field: &str,
+    expected: &str,
+    is_valid: fn(Decimal) -> bool,
+) -> Result<Option<Decimal>, FormulaError> {
+    match doc.get(field) {
+        None => Ok(None),
+        Some(node) => node
+            .as_decimal()
+            .filter(|x| is_valid(*x))
+            .map(Some)
+            .ok_or_else(|| invalid_field(field, None, None, expected, node.location)),
+    }
+}
+
+// parses an ingredient triple [name, mass, is_flour] or a mapping of the
+// named fields
+fn parse_ingredient(node: &Node, component: &str) -> Result<IngredientSpec, FormulaError> {
+    let (name, mass, is_flour) = match &node.kind {
+        NodeKind::Seq(fields) if fields.len() == 3 => (&fields[0], &fields[1], Some(&fields[2])),
+        NodeKind::Map(entries) => {
+            if let Some((key, _)) = entries
+                .iter()
+                .find(|(key, _)| !key.as_str().is_some_and(|k| INGREDIENT_FIELDS.contains(&k)))
+            {
+                return Err(invalid_field(
+                    key.as_str().unwrap_or("ingredients"),
+                    Some(component),
+                    None,
+                    "one of name, mass, unit, flour, category, water and notes",
+                    key.location,
+                ));
+            }
+            let name = required_field(node, "name", Some(component))?;
+            let mass = required_field(node, "mass", Some(component))?;
+            (name, mass, node.get("flour"))
+        }
+        _ => {
+            return Err(invalid_field(
+                "ingredients",
+                Some(component),
+                None,
+                "a list of [name, mass, is_flour] or a mapping with name and mass",
+                node.location,
+            ))
+        }
+    };
+    let name = name
+        .as_str()
+        .ok_or_else(|| invalid_field("name", Some(component), None, "a string", name.location))?;
+    let invalid = |field: &str, expected: &str, location: Location| {
+        invalid_field(field, Some(component), Some(name), expected, location)
+    };
+    let mass_location = mass.location;
+    let mass = mass
+        .as_decimal()
+        .filter(|m| is_mass(*m))
+        .ok_or_else(|| invalid("mass", MASS_EXPECTED, mass_location))?;
+    // the triple names the flag is_flour, the mapping flour
+    let flag = if matches!(node.kind, NodeKind::Seq(_)) { "is_flour" } else { "flour" };
+    let is_flour = match is_flour {
+        None => false,
+        Some(flour) => flour
+            .as_bool()
+            .ok_or_else(|| invalid(flag, "true or false", flour.location))?,
+    };
+    let optional_text = |field: &str| -> Result<Option<String>, FormulaError> {
+        match node.get(field) {
+            None => Ok(None),
+            Some(value) => value
+                .as_str()
+                .map(|s| Some(s.to_string()))
+                .ok_or_else(|| invalid(field, "a string", value.location)),
+        }
+    };
+    let unit = match node.get("unit") {
+        None => None,
+        Some(unit) => Some(
+            unit.as_str()
+                .and_then(|symbol| symbol.parse::<Unit>().ok())
+                .ok_or_else(|| invalid("unit", UNIT_EXPECTED, unit.location))?,
+        ),
+    };
+    let category = match node.get("category") {
+        None => None,
+        Some(category) => Some(
+            category
+                .as_str()
+                .and_then(|name| name.parse::<Category>().ok())
+                .ok_or_else(|| invalid("category", CATEGORY_EXPECTED, category.location))?,
+        ),
+    };
+    let water = match node.get("water") {
+        None => None,
+        Some(water) => Some(
+            water
+                .as_decimal()
+                .filter(|x| is_fraction(*x))
+                .ok_or_else(|| invalid("water", FRACTION_EXPECTED, water.location))?,
+        ),
+    };
+    let ingredient = IngredientSpec {
+        unit,
+        category,
+        water,
+        notes: optional_text("notes")?,
+        location: node.location,
+        ..IngredientSpec::new(name.to_string(), mass, is_flour)
+    };
+    if ingredient.grams().is_none() {
+        let (field, expected) = ingredient.grams_expected();
+        let location = match field {
+            "mass" => mass_location,
+            _ => node.get("unit").expect("only volumes lack a density").location,
+        };
+        return Err(invalid(field, expected, location));
+    }
+    if !ingredient.has_valid_category() {
+        let category = node.get("category").expect("only given categories are checked");
+        return Err(invalid("category", FLOUR_CATEGORY_EXPECTED, category.location));
+    }
+    Ok(ingredient)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_error(source: &str) -> (String, Location) {
+        match FormulaSpec::from_yaml_str(source) {
+            Err(FormulaError::InvalidField { field, location, .. }) => (field, location),
+            res => panic!("expected invalid field, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_ingredient_syntax() {
+        let source = "name: bread\n\
+                      components:\n  \
+                        - name: mix\n    \
+                          ingredients:\n      \
+                            - [bread flour, 1000, true]\n      \
+                            - {name: water, mass: 700.10, unit: g, water: 0.9, notes: cold}\n      \
+                            - name: salt\n        \
+                              mass: 2e1\n        \
+                              category: salt\n";
+        let spec = FormulaSpec::from_yaml_str(source).unwrap();
+        assert_eq!(spec.version, SCHEMA_VERSION);
+        let ingredients = &spec.components[0].ingredients;
+        assert_eq!(ingredients[0], IngredientSpec {
+            location: ingredients[0].location,
+            ..IngredientSpec::new(String::from("bread flour"), dec!(1000), true)
+        });
+        assert!(!ingredients[1].flour);
+        assert_eq!(ingredients[1].mass.to_string(), "700.10");
+        assert_eq!(ingredients[1].notes.as_deref(), Some("cold"));
+        assert_eq!(ingredients[1].water_fraction(), dec!(0.9));
+        assert_eq!(ingredients[2].water_fraction(), dec!(0));
+        assert_eq!(ingredients[2].mass, dec!(20));
+        assert_eq!(ingredients[2].category, Some(Category::Salt));
+        assert_eq!(ingredients[1].category(), Category::Liquid);
+        assert_eq!(ingredients[2].location, Location { line: 7, col: 13 });
+    }
+
+    #[test]
+    fn test_exact_numbers() {
+        // more digits than an f64 holds
+        let yaml = "name: bread\n\
+                    dough_serving: 500.123456789012345678\n\
+                    loss: 0.025\n\
+                    components:\n  \
+                      - name: mix\n    \
+                        ingredients:\n      \
+                          - [bread flour, 1000.000000000000000001, true]\n      \
+                          - {name: water, mass: 700.1, water: 0.123456789012345678}\n";
+        let json = r#"{"name": "bread", "dough_serving": 500.123456789012345678, "loss": 0.025,
+                       "components": [{"name": "mix", "ingredients": [
+                           ["bread flour", 1000.000000000000000001, true],
+                           {"name": "water", "mass": 700.1, "water": 0.123456789012345678}]}]}"#;
+        let toml = "name = \"bread\"\n\
+                    dough_serving = 500.123456789012345678\n\
+                    loss = 0.025\n\
+                    [[components]]\n\
+                    name = \"mix\"\n\
+                    ingredients = [\n  \
+                      [\"bread flour\", 1_000.000000000000000001, true],\n  \
+                      { name = \"water\", mass = 700.1, water = 0.123456789012345678 },\n\
+                    ]\n";
+        let yaml = FormulaSpec::from_yaml_str(yaml).unwrap();
+        let json = FormulaSpec::from_json_str(json).unwrap();
+        let toml = FormulaSpec::from_toml_str(toml).unwrap();
+        assert_eq!(yaml.components[0].ingredients[0].mass.to_string(), "1000.000000000000000001");
+        assert_eq!(json.to_json(), yaml.to_json());
+        assert_eq!(toml.to_json(), yaml.to_json());
+        assert!(yaml.to_json().contains("\"dough_serving\": 500.123456789012345678,"));
+        // a float where it does not belong is still reported as one
+        let toml = "name = \"bread\"\n[[components]]\nname = \"mix\"\ningredients = [[\"flour\", 1, 2.5]]\n";
+        let res = FormulaSpec::from_toml_str(toml);
+        assert!(matches!(res, Err(FormulaError::Toml { message, .. }) if message.contains("floating point `2.5`")));
+    }
+
+    #[test]
+    fn test_yaml_string() {
+        assert_eq!(yaml_string("flour, \"00\""), "\"flour, \\\"00\\\"\"");
+        assert_eq!(yaml_string("true"), "\"true\"");
+        assert_eq!(yaml_string("rye flour (type 1150)"), "rye flour (type 1150)");
+        assert_eq!(yaml_string("baker's flour"), "baker's flour");
+        assert_eq!(yaml_string("'s flour"), "\"'s flour\"");
+        assert_eq!(yaml_string("- x"), "\"- x\"");
+        assert_eq!(yaml_string("-x"), "\"-x\"");
+        let source = "version: 1\nname: bread\ncomponents:\n  - name: mix\n    ingredients:\n      \
+                      - [\"'s flour\", 100, true]\n      - [\"- x\", 5, false]\n";
+        let spec = FormulaSpec::from_yaml_str(source).unwrap();
+        assert_eq!(FormulaSpec::from_yaml_str(&spec.to_yaml()).unwrap().to_yaml(), spec.to_yaml());
+    }
+
+    #[test]
+    fn test_schema_errors() {
+        let formula = |ingredient: &str| {
+            format!("version: 1\nname: bread\ncomponents:\n  - name: mix\n    ingredients:\n      - {}\n", ingredient)
+        };
+        assert_eq!(
+            parse_error(&formula("{name: water, mass: 7, colour: blue}")),
+            (String::from("colour"), Location { line: 6, col: 32 })
+        );
+        assert_eq!(
+            parse_error(&formula("{name: water, mass: 7, unit: cups}")),
+            (String::from("unit"), Location { line: 6, col: 38 })
+        );
+        assert_eq!(
+            parse_error(&formula("{name: flour, mass: 7, unit: ml}")),
+            (String::from("unit"), Location { line: 6, col: 38 })
+        );
+        // too many pounds to hold in grams
+        assert_eq!(
+            parse_error(&formula("{name: flour, mass: 1e27, unit: lb, flour: true}")),
+            (String::from("mass"), Location { line: 6, col: 29 })
+        );
+        assert_eq!(
+            parse_error(&formula("{name: water, mass: 7, category: seasoning}")),
+            (String::from("category"), Location { line: 6, col: 42 })
+        );
+        assert_eq!(
+            parse_error(&formula("{name: rye, mass: 7, category: flour}")),
+            (String::from("category"), Location { line: 6, col: 40 })
+        );
+        assert_eq!(
+            parse_error(&formula("{name: milk, mass: 7, water: 87}")),
+            (String::from("water"), Location { line: 6, col: 38 })
+        );
+        assert_eq!(
+            parse_error(&formula("{name: water, mass: 7, flour: maybe}")),
+            (String::from("flour"), Location { line: 6, col: 39 })
+        );
+        assert_eq!(
+            parse_error(&formula("[water, 7, maybe]")).0,
+            "is_flour"
+        );
+        assert_eq!(
+            parse_error(&formula("[water, 7]")).0,
+            "ingredients"
+        );
+        assert_eq!(
+            parse_error(&formula("[water, 7, false]").replace("version: 1", "version: 2")),
+            (String::from("version"), Location { line: 1, col: 10 })
+        );
+        let spec = FormulaSpec::from_yaml_str(&formula("{name: water, mass: 7, unit: tsp}")).unwrap();
+        assert_eq!(spec.components[0].ingredients[0].unit, Some(Unit::Teaspoon));
+        let json = r#"{"name": "bread", "components": [{"name": "mix", "ingredients": [
+            {"name": "rye flour", "mass": 2, "unit": "tsp"}]}]}"#;
+        let spec = FormulaSpec::from_json_str(json).unwrap();
+        assert!(matches!(spec.validate(), Err(FormulaError::InvalidField { field, .. }) if field == "unit"));
+        assert!(FormulaSpec::from_json_str(&json.replace("tsp", "cups")).is_err());
+        let spec = FormulaSpec::from_json_str(&json.replace("2,", "1e27,").replace("tsp", "lb")).unwrap();
+        assert!(matches!(spec.validate(), Err(FormulaError::InvalidField { field, .. }) if field == "mass"));
+        assert!(matches!(
+            FormulaSpec::from_yaml_str(&formula("{mass: 7}")),
+            Err(FormulaError::MissingField { field, .. }) if field == "name"
+        ));
+    }
+}
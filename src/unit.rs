@@ -0,0 +1,185 @@
+use rust_decimal::Decimal;
+use rust_decimal_macros::dec;
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+// A unit an ingredient may be measured in. Masses are kept in grams, volumes
+// are turned into grams by the density of the ingredient
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Unit {
+    #[default]
+    #[serde(rename = "g")]
+    Gram,
+    #[serde(rename = "kg")]
+    Kilogram,
+    #[serde(rename = "oz")]
+    Ounce,
+    #[serde(rename = "lb")]
+    Pound,
+    #[serde(rename = "ml")]
+    Millilitre,
+    #[serde(rename = "tsp")]
+    Teaspoon,
+}
+
+impl Unit {
+    pub const ALL: [Unit; 6] = [
+        Unit::Gram,
+        Unit::Kilogram,
+        Unit::Ounce,
+        Unit::Pound,
+        Unit::Millilitre,
+        Unit::Teaspoon,
+    ];
+
+    // the symbol the unit is written as in formulas and sheets
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Unit::Gram => "g",
+            Unit::Kilogram => "kg",
+            Unit::Ounce => "oz",
+            Unit::Pound => "lb",
+            Unit::Millilitre => "ml",
+            Unit::Teaspoon => "tsp",
+        }
+    }
+
+    // grams in one of the unit, None for volume units
+    pub fn grams(&self) -> Option<Decimal> {
+        match self {
+            Unit::Gram => Some(dec!(1)),
+            Unit::Kilogram => Some(dec!(1000)),
+            Unit::Ounce => Some(dec!(28.349523125)),
+            Unit::Pound => Some(dec!(453.59237)),
+            Unit::Millilitre | Unit::Teaspoon => None,
+        }
+    }
+
+    // millilitres in one of the unit, None for mass units. A teaspoon is the
+    // US one
+    pub fn millilitres(&self) -> Option<Decimal> {
+        match self {
+            Unit::Millilitre => Some(dec!(1)),
+            Unit::Teaspoon => Some(dec!(4.92892159375)),
+            _ => None,
+        }
+    }
+
+    pub fn is_mass(&self) -> bool {
+        self.grams().is_some()
+    }
+}
+
+impl fmt::Display for Unit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.symbol())
+    }
+}
+
+impl FromStr for Unit {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Unit::ALL.into_iter().find(|unit| unit.symbol() == s).ok_or(())
+    }
+}
+
+// grams per millilitre of the ingredients that are commonly measured by
+// volume, by lower case name
+const DENSITIES: [(&str, Decimal); 16] = [
+    ("water", dec!(1)),
+    ("milk", dec!(1.03)),
+    ("whole milk", dec!(1.03)),
+    ("buttermilk", dec!(1.03)),
+    ("cream", dec!(1.01)),
+    ("egg", dec!(1.03)),
+    ("oil", dec!(0.92)),
+    ("vegetable oil", dec!(0.92)),
+    ("olive oil", dec!(0.91)),
+    ("honey", dec!(1.42)),
+    ("malt syrup", dec!(1.4)),
+    ("maple syrup", dec!(1.32)),
+    ("salt", dec!(1.22)),
+    ("sugar", dec!(0.85)),
+    ("yeast", dec!(0.63)),
+    ("instant yeast", dec!(0.63)),
+];
+
+// Returns the density of an ingredient in grams per millilitre, if it is in
+// the table. Names are compared ignoring case and surrounding whitespace
+pub fn density(ingredient: &str) -> Option<Decimal> {
+    let ingredient = ingredient.trim().to_lowercase();
+    DENSITIES
+        .iter()
+        .find(|(name, _)| *name == ingredient)
+        .map(|(_, density)| *density)
+}
+
+// An amount of an ingredient in some unit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quantity {
+    pub amount: Decimal,
+    pub unit: Unit,
+}
+
+impl Quantity {
+    pub fn new(amount: Decimal, unit: Unit) -> Self {
+        Quantity { amount, unit }
+    }
+
+    // Returns the quantity of ingredient in grams, or None for a volume of an
+    // ingredient without a known density and for an amount too large to be
+    // converted
+    pub fn to_grams(&self, ingredient: &str) -> Option<Decimal> {
+        match (self.unit.grams(), self.unit.millilitres()) {
+            (Some(grams), _) => self.amount.checked_mul(grams),
+            (None, Some(millilitres)) => {
+                density(ingredient).and_then(|d| self.amount.checked_mul(millilitres)?.checked_mul(d))
+            }
+            (None, None) => None,
+        }
+    }
+
+    // Returns grams as a quantity of the mass unit unit
+    pub fn from_grams(grams: Decimal, unit: Unit) -> Option<Self> {
+        unit.grams().map(|per_unit| Quantity::new(grams / per_unit, unit))
+    }
+}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.amount.normalize(), self.unit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_units() {
+        for unit in Unit::ALL {
+            assert_eq!(unit.symbol().parse(), Ok(unit));
+            assert_ne!(unit.grams().is_some(), unit.millilitres().is_some());
+        }
+        assert!("cups".parse::<Unit>().is_err());
+        assert!("G".parse::<Unit>().is_err());
+        assert_eq!(serde_json::to_string(&Unit::Pound).unwrap(), "\"lb\"");
+    }
+
+    #[test]
+    fn test_to_grams() {
+        assert_eq!(Quantity::new(dec!(2), Unit::Pound).to_grams("flour"), Some(dec!(907.18474)));
+        assert_eq!(Quantity::new(dec!(1.5), Unit::Kilogram).to_grams("flour"), Some(dec!(1500)));
+        assert_eq!(Quantity::new(dec!(700), Unit::Millilitre).to_grams(" Water"), Some(dec!(700)));
+        assert_eq!(
+            Quantity::new(dec!(2), Unit::Teaspoon).to_grams("salt").map(|g| g.round_dp(3)),
+            Some(dec!(12.027))
+        );
+        assert_eq!(Quantity::new(dec!(100), Unit::Millilitre).to_grams("flour"), None);
+        assert_eq!(Quantity::new(dec!(1e27), Unit::Pound).to_grams("flour"), None);
+        let ounces = Quantity::from_grams(dec!(56.69904625), Unit::Ounce).unwrap();
+        assert_eq!(ounces.to_string(), "2 oz");
+        assert_eq!(Quantity::from_grams(dec!(1), Unit::Teaspoon), None);
+    }
+}
@@ -0,0 +1,158 @@
+use crate::csv_cell::{CSVCell, CellExpr, CellPosition, CellValue};
+use crate::error::FormulaError;
+use crate::eval::{evaluate, PercentCells, Value};
+use rust_decimal::prelude::*;
+use rust_xlsxwriter::{Format, FormatBorder, Formula, Workbook};
+use std::collections::HashMap;
+
+const PERCENT_FORMAT: &str = "0.0%";
+const MASS_FORMAT: &str = "0.0";
+const MIN_COL_WIDTH: usize = 10;
+
+// Returns the xlsx workbook for the given cells.
+//  - expressions are stored as formulas, with the value computed by the
+//    evaluator as their cached result, so that viewers which do not
+//    recalculate on load still show numbers
+//  - percentages, and formulas that only combine percentages, get a
+//    percent number format. Other formulas get a mass format
+//  - text above the first row holding an expression is the bold header
+//  - SUM cells are totals, which are bold with a border on top
+//  - columns are wide enough for their text
+pub fn cells_to_xlsx(cells: &[CSVCell]) -> Result<Vec<u8>, FormulaError> {
+    let values = evaluate(cells)?;
+    let mut percent_cells = PercentCells::new(cells);
+    let header_end = cells
+        .iter()
+        .filter(|c| matches!(c.value, CellValue::Expr(_)))
+        .map(|c| c.position.row)
+        .min()
+        .unwrap_or(0);
+
+    let mut workbook = Workbook::new();
+    let worksheet = workbook.add_worksheet();
+    let mut col_widths: HashMap<u16, usize> = HashMap::new();
+    for cell in cells {
+        let row = cell.position.row;
+        let col = to_xlsx_col(&cell.position)?;
+        match &cell.value {
+            CellValue::Empty => continue,
+            CellValue::Str(s) => {
+                let format = Format::new().set_bold();
+                if row < header_end {
+                    worksheet.write_string_with_format(row, col, s, &format)
+                } else {
+                    worksheet.write_string(row, col, s)
+                }
+                .map_err(to_formula_error)?;
+            }
+            CellValue::Expr(expr) => {
+                let mut format = if percent_cells.is_percent(&cell.position) {
+                    Format::new().set_num_format(PERCENT_FORMAT)
+                } else {
+                    Format::new().set_num_format(MASS_FORMAT)
+                };
+                if let CellExpr::Sum(_) = expr {
+                    format = format.set_bold().set_border_top(FormatBorder::Thin);
+                }
+                match expr {
+                    CellExpr::Number(x) => {
+                        worksheet.write_number(row, col, x.to_f64().unwrap_or_default())
+                    }
+                    CellExpr::Percentage(x) => {
+                        worksheet.write_number_with_format(row, col, x.to_f64().unwrap_or_default(), &format)
+                    }
+                    _ => {
+                        let result = match &values[&cell.position] {
+                            Value::Number(x) => x.round_dp(10).normalize().to_string(),
+                            Value::Text(s) => s.clone(),
+                            Value::Empty => String::new(),
+                        };
+                        let formula = Formula::new(expr.to_string()).set_result(result);
+                        worksheet.write_formula_with_format(row, col, formula, &format)
+                    }
+                }
+                .map_err(to_formula_error)?;
+            }
+        }
+        if let Some(Value::Text(s)) = values.get(&cell.position) {
+            let width = col_widths.entry(col).or_insert(MIN_COL_WIDTH);
+            *width = (*width).max(s.chars().count() + 2);
+        }
+    }
+    for (col, width) in col_widths {
+        worksheet
+            .set_column_width(col, width as f64)
+            .map_err(to_formula_error)?;
+    }
+    workbook.save_to_buffer().map_err(to_formula_error)
+}
+
+fn to_formula_error(e: rust_xlsxwriter::XlsxError) -> FormulaError {
+    FormulaError::Xlsx { source: e }
+}
+
+// xlsx limits columns to 16384, which fits in a u16
+fn to_xlsx_col(pos: &CellPosition) -> Result<u16, FormulaError> {
+    u16::try_from(pos.col).map_err(|_| {
+        to_formula_error(rust_xlsxwriter::XlsxError::ParameterError(format!(
+            "cell {} is out of range",
+            pos
+        )))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_cell::{BinOp, CellArray, CellRef};
+    use rust_decimal_macros::dec;
+    use std::io::{Cursor, Read};
+
+    fn cell_ref(row: u32, col: u32) -> CellRef {
+        CellRef { pos: CellPosition { row, col }, fix_row: false, fix_col: false }
+    }
+
+    fn cell(row: u32, col: u32, value: CellValue) -> CSVCell {
+        CSVCell { value, position: CellPosition { row, col } }
+    }
+
+    fn sample() -> Vec<CSVCell> {
+        let sum = CellArray::new(cell_ref(1, 1), cell_ref(2, 1)).unwrap();
+        vec![
+            cell(0, 1, CellValue::Str(String::from("mix"))),
+            cell(1, 0, CellValue::Str(String::from("all purpose flour"))),
+            cell(1, 1, CellValue::Expr(CellExpr::Percentage(dec!(1)))),
+            cell(2, 1, CellValue::Expr(CellExpr::Percentage(dec!(0.75)))),
+            cell(3, 1, CellValue::Expr(CellExpr::Sum(sum))),
+            cell(4, 1, CellValue::Expr(CellExpr::Number(dec!(1000)))),
+            cell(
+                1,
+                2,
+                CellValue::Expr(CellExpr::BinaryOp(
+                    BinOp::Div,
+                    Box::new(CellExpr::BinaryOp(
+                        BinOp::Mult,
+                        Box::new(CellExpr::Ref(cell_ref(1, 1))),
+                        Box::new(CellExpr::Ref(cell_ref(4, 1))),
+                    )),
+                    Box::new(CellExpr::Ref(cell_ref(3, 1))),
+                )),
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_cells_to_xlsx() {
+        let buffer = cells_to_xlsx(&sample()).unwrap();
+        let mut archive = zip::ZipArchive::new(Cursor::new(buffer)).unwrap();
+        let mut sheet = String::new();
+        archive
+            .by_name("xl/worksheets/sheet1.xml")
+            .unwrap()
+            .read_to_string(&mut sheet)
+            .unwrap();
+        assert!(sheet.contains("<f>SUM(B2:B3)</f><v>1.75</v>"));
+        assert!(sheet.contains("<f>((B2*B5)/B4)</f>"));
+        assert!(sheet.contains(r#"<col min="1" max="1" width="19.7109375""#));
+    }
+}